@@ -3,9 +3,14 @@
 
 mod whatsapp_client;
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use tauri::{Manager, RunEvent};
 use whatsapp_client::WhatsAppState;
 
+// Set once the bot tasks have been shut down, so the second exit request goes through
+static SHUTDOWN_COMPLETE: AtomicBool = AtomicBool::new(false);
+
 fn main() {
     let whatsapp_state = Arc::new(WhatsAppState::new());
 
@@ -16,9 +21,82 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             whatsapp_client::init_whatsapp,
             whatsapp_client::is_bot_ready,
+            whatsapp_client::ping_bot,
+            whatsapp_client::connection_status,
+            whatsapp_client::get_own_number,
+            whatsapp_client::get_current_qr,
+            whatsapp_client::get_message_status,
             whatsapp_client::send_message,
+            whatsapp_client::send_raw,
+            whatsapp_client::send_message_bulk,
             whatsapp_client::send_media_message,
+            whatsapp_client::send_cached_media,
+            whatsapp_client::send_media_bytes,
+            whatsapp_client::cancel_upload,
+            whatsapp_client::send_sticker,
+            whatsapp_client::post_status,
+            whatsapp_client::send_contact,
+            whatsapp_client::send_poll,
+            whatsapp_client::send_buttons,
+            whatsapp_client::send_live_location,
+            whatsapp_client::update_live_location,
+            whatsapp_client::forward_message,
+            whatsapp_client::set_media_limits,
+            whatsapp_client::configure_rate_limit,
+            whatsapp_client::set_debug_logging,
+            whatsapp_client::enable_raw_events,
+            whatsapp_client::set_idle_timeout,
+            whatsapp_client::set_dry_run,
+            whatsapp_client::get_settings,
+            whatsapp_client::reset_settings,
+            whatsapp_client::set_event_filter,
+            whatsapp_client::send_reaction,
+            whatsapp_client::set_disappearing_messages,
+            whatsapp_client::revoke_message,
+            whatsapp_client::edit_message,
+            whatsapp_client::set_typing,
+            whatsapp_client::set_online,
+            whatsapp_client::set_profile_name,
+            whatsapp_client::set_status_text,
+            whatsapp_client::subscribe_presence,
+            whatsapp_client::unsubscribe_presence,
+            whatsapp_client::mark_read,
+            whatsapp_client::download_media,
+            whatsapp_client::get_contacts,
+            whatsapp_client::resync,
+            whatsapp_client::get_chats,
+            whatsapp_client::get_unread_counts,
+            whatsapp_client::get_messages,
+            whatsapp_client::check_number_exists,
+            whatsapp_client::get_group_info,
+            whatsapp_client::create_group,
+            whatsapp_client::update_group_participants,
+            whatsapp_client::set_group_subject,
+            whatsapp_client::set_group_description,
+            whatsapp_client::get_profile_picture,
+            whatsapp_client::get_business_profile,
+            whatsapp_client::logout,
+            whatsapp_client::disconnect,
+            whatsapp_client::clear_caches,
+            whatsapp_client::reset_session,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::ExitRequested { api, .. } = event {
+                if SHUTDOWN_COMPLETE.load(Ordering::SeqCst) {
+                    return;
+                }
+                // Hold the exit until every bot task has disconnected and released its store;
+                // dropping them mid-write has corrupted sessions on force-quit
+                api.prevent_exit();
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    let state = app.state::<Arc<WhatsAppState>>().inner().clone();
+                    state.shutdown_all().await;
+                    SHUTDOWN_COMPLETE.store(true, Ordering::SeqCst);
+                    app.exit(0);
+                });
+            }
+        });
 }
\ No newline at end of file
@@ -1,24 +1,404 @@
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use tauri::{Emitter, State, Window, Manager};
-use tokio::sync::{Mutex, mpsc, oneshot};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Emitter, State, Window, Manager, Runtime};
+use tokio::sync::{Mutex, Notify, mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
 use whatsapp_rust::bot::Bot;
+use whatsapp_rust::client::{Client, ClientError};
+use whatsapp_rust::http::{HttpClient, HttpRequest, HttpResponse};
 use whatsapp_rust::store::SqliteStore;
 use whatsapp_rust_tokio_transport::TokioWebSocketTransportFactory;
 use whatsapp_rust_ureq_http_client::UreqHttpClient;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde::ser::SerializeMap;
 
 // Import types from whatsapp_rust with correct paths
 use whatsapp_rust::types::events::Event;
+use whatsapp_rust::types::presence::{ChatPresence, ChatPresenceMedia, ReceiptType};
 use whatsapp_rust::Jid;
 use whatsapp_rust::waproto::whatsapp as wa;
 use whatsapp_rust::download::MediaType;
+use whatsapp_rust::pair_code::PairCodeOptions;
+use whatsapp_rust::request::IqError;
+
+// Errors returned to the frontend. Serialized as { kind, message } so the UI can
+// switch on `kind` and still show `message` to the user.
+#[derive(Debug)]
+pub enum WhatsAppError {
+    NotReady,
+    NotInitialized,
+    AlreadyInitialized,
+    SessionActive,
+    InvalidAccountId(String),
+    InvalidContact(String),
+    InitFailed(String),
+    StorageUnavailable(String),
+    InvalidDbName(String),
+    InvalidProxy(String),
+    FileNotFound { path: String },
+    FileNotReadable { path: String, reason: String },
+    InvalidBase64(String),
+    InvalidSticker(String),
+    InvalidThumbnail(String),
+    InvalidMime(String),
+    MediaTooLarge { size: u64, limit: u64 },
+    FileWrite(String),
+    MediaNotFound(String),
+    MessageNotFound(String),
+    ForwardUnavailable(String),
+    CursorNotFound(String),
+    RevokeExpired(String),
+    EmptyMessage,
+    EmptyMedia,
+    InvalidPoll(String),
+    InvalidButtons(String),
+    InvalidStatus(String),
+    InvalidLocation(String),
+    NoLiveLocation,
+    InvalidProfile(String),
+    InvalidRateLimit(String),
+    InvalidDisappearingTimer(u32),
+    EditRejected(String),
+    DownloadFailed(String),
+    StoreFailed(String),
+    UploadFailed(String),
+    MediaRejected(String),
+    SendFailed(String),
+    NotConnected(String),
+    ServerRateLimited(String),
+    InvalidRecipient(String),
+    QueryFailed(String),
+    NotGroupMember(String),
+    InvalidGroup(String),
+    NotGroupAdmin(String),
+    ViewOnceUnsupported(String),
+    GifPlaybackUnsupported(String),
+    InvalidReuseToken(String),
+    DatabaseLocked(String),
+    ReuseTokenExpired,
+    LogoutFailed(String),
+    Timeout(u64),
+    BusyTryAgain,
+    RateLimited { retry_after_ms: u64 },
+    QueueFull,
+    Cancelled(String),
+    BotTaskGone,
+}
+
+impl WhatsAppError {
+    fn kind(&self) -> &'static str {
+        match self {
+            WhatsAppError::NotReady => "NotReady",
+            WhatsAppError::NotInitialized => "NotInitialized",
+            WhatsAppError::AlreadyInitialized => "AlreadyInitialized",
+            WhatsAppError::SessionActive => "SessionActive",
+            WhatsAppError::InvalidAccountId(_) => "InvalidAccountId",
+            WhatsAppError::InvalidContact(_) => "InvalidContact",
+            WhatsAppError::InitFailed(_) => "InitFailed",
+            WhatsAppError::StorageUnavailable(_) => "StorageUnavailable",
+            WhatsAppError::InvalidDbName(_) => "InvalidDbName",
+            WhatsAppError::InvalidProxy(_) => "InvalidProxy",
+            WhatsAppError::FileNotFound { .. } => "FileNotFound",
+            WhatsAppError::FileNotReadable { .. } => "FileNotReadable",
+            WhatsAppError::InvalidBase64(_) => "InvalidBase64",
+            WhatsAppError::InvalidSticker(_) => "InvalidSticker",
+            WhatsAppError::InvalidThumbnail(_) => "InvalidThumbnail",
+            WhatsAppError::InvalidMime(_) => "InvalidMime",
+            WhatsAppError::MediaTooLarge { .. } => "MediaTooLarge",
+            WhatsAppError::FileWrite(_) => "FileWrite",
+            WhatsAppError::MediaNotFound(_) => "MediaNotFound",
+            WhatsAppError::MessageNotFound(_) => "MessageNotFound",
+            WhatsAppError::ForwardUnavailable(_) => "ForwardUnavailable",
+            WhatsAppError::CursorNotFound(_) => "CursorNotFound",
+            WhatsAppError::RevokeExpired(_) => "RevokeExpired",
+            WhatsAppError::EmptyMessage => "EmptyMessage",
+            WhatsAppError::EmptyMedia => "EmptyMedia",
+            WhatsAppError::InvalidPoll(_) => "InvalidPoll",
+            WhatsAppError::InvalidButtons(_) => "InvalidButtons",
+            WhatsAppError::InvalidStatus(_) => "InvalidStatus",
+            WhatsAppError::InvalidLocation(_) => "InvalidLocation",
+            WhatsAppError::NoLiveLocation => "NoLiveLocation",
+            WhatsAppError::InvalidProfile(_) => "InvalidProfile",
+            WhatsAppError::InvalidRateLimit(_) => "InvalidRateLimit",
+            WhatsAppError::InvalidDisappearingTimer(_) => "InvalidDisappearingTimer",
+            WhatsAppError::EditRejected(_) => "EditRejected",
+            WhatsAppError::DownloadFailed(_) => "DownloadFailed",
+            WhatsAppError::StoreFailed(_) => "StoreFailed",
+            WhatsAppError::UploadFailed(_) => "UploadFailed",
+            WhatsAppError::MediaRejected(_) => "MediaRejected",
+            WhatsAppError::SendFailed(_) => "SendFailed",
+            WhatsAppError::NotConnected(_) => "NotConnected",
+            WhatsAppError::ServerRateLimited(_) => "ServerRateLimited",
+            WhatsAppError::InvalidRecipient(_) => "InvalidRecipient",
+            WhatsAppError::QueryFailed(_) => "QueryFailed",
+            WhatsAppError::NotGroupMember(_) => "NotGroupMember",
+            WhatsAppError::InvalidGroup(_) => "InvalidGroup",
+            WhatsAppError::NotGroupAdmin(_) => "NotGroupAdmin",
+            WhatsAppError::ViewOnceUnsupported(_) => "ViewOnceUnsupported",
+            WhatsAppError::GifPlaybackUnsupported(_) => "GifPlaybackUnsupported",
+            WhatsAppError::InvalidReuseToken(_) => "InvalidReuseToken",
+            WhatsAppError::DatabaseLocked(_) => "DatabaseLocked",
+            WhatsAppError::ReuseTokenExpired => "ReuseTokenExpired",
+            WhatsAppError::LogoutFailed(_) => "LogoutFailed",
+            WhatsAppError::Timeout(_) => "Timeout",
+            WhatsAppError::BusyTryAgain => "BusyTryAgain",
+            WhatsAppError::RateLimited { .. } => "RateLimited",
+            WhatsAppError::QueueFull => "QueueFull",
+            WhatsAppError::Cancelled(_) => "Cancelled",
+            WhatsAppError::BotTaskGone => "BotTaskGone",
+        }
+    }
+}
+
+impl std::fmt::Display for WhatsAppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WhatsAppError::NotReady => write!(f, "WhatsApp is not ready yet. Please wait for connection to complete."),
+            WhatsAppError::NotInitialized => write!(f, "WhatsApp not initialized"),
+            WhatsAppError::AlreadyInitialized => write!(f, "WhatsApp is already initialized or starting for this account"),
+            WhatsAppError::SessionActive => write!(f, "Disconnect or log out before resetting the session"),
+            WhatsAppError::InvalidAccountId(id) => write!(f, "Invalid account id '{}': use letters, digits, '-' or '_'", id),
+            WhatsAppError::InvalidContact(e) => write!(f, "Invalid contact: {}", e),
+            WhatsAppError::InitFailed(e) => write!(f, "Failed to initialize: {}", e),
+            WhatsAppError::StorageUnavailable(e) => write!(f, "No writable storage for the session: {}", e),
+            WhatsAppError::InvalidProxy(url) => write!(f, "Invalid proxy URL '{}': use http://, https:// or socks5:// followed by host:port", url),
+            WhatsAppError::InvalidDbName(name) => write!(f, "Invalid database name '{}': use a plain file name like work.db", name),
+            WhatsAppError::FileNotFound { path } => write!(f, "File not found: {}", path),
+            WhatsAppError::FileNotReadable { path, reason } => write!(f, "Cannot read {}: {}", path, reason),
+            WhatsAppError::InvalidBase64(e) => write!(f, "Media data is not valid base64: {}", e),
+            WhatsAppError::InvalidSticker(path) => write!(f, "Stickers must be WebP images: {}", path),
+            WhatsAppError::InvalidThumbnail(path) => write!(f, "Thumbnails must be JPEG images: {}", path),
+            WhatsAppError::InvalidMime(mime) => write!(f, "'{}' is not a MIME type like image/png", mime),
+            WhatsAppError::MediaTooLarge { size, limit } => write!(
+                f,
+                "Media is {:.1} MB, over the {:.1} MB limit for this type",
+                *size as f64 / MB as f64,
+                *limit as f64 / MB as f64
+            ),
+            WhatsAppError::FileWrite(e) => write!(f, "Failed to write file: {}", e),
+            WhatsAppError::MediaNotFound(id) => write!(f, "No downloadable media cached for message {}", id),
+            WhatsAppError::MessageNotFound(id) => write!(f, "Message {} was not sent from this app in this chat", id),
+            WhatsAppError::ForwardUnavailable(id) => write!(f, "Message {} is no longer cached or can't be forwarded", id),
+            WhatsAppError::CursorNotFound(id) => write!(f, "Message {} is not in this chat's history", id),
+            WhatsAppError::RevokeExpired(id) => write!(f, "Message {} is too old to delete for everyone", id),
+            WhatsAppError::EmptyMessage => write!(f, "Message text cannot be empty"),
+            WhatsAppError::EmptyMedia => write!(f, "The media file is empty"),
+            WhatsAppError::InvalidPoll(e) => write!(f, "Invalid poll: {}", e),
+            WhatsAppError::InvalidButtons(e) => write!(f, "Invalid buttons: {}", e),
+            WhatsAppError::InvalidStatus(e) => write!(f, "Invalid status update: {}", e),
+            WhatsAppError::InvalidLocation(e) => write!(f, "Invalid location: {}", e),
+            WhatsAppError::NoLiveLocation => write!(f, "No live location is being shared; call send_live_location first"),
+            WhatsAppError::InvalidProfile(e) => write!(f, "Invalid profile update: {}", e),
+            WhatsAppError::InvalidDisappearingTimer(secs) => write!(f, "Unsupported disappearing message timer {}s: use 0, 86400, 604800 or 7776000", secs),
+            WhatsAppError::InvalidRateLimit(mode) => write!(f, "Unknown rate limit mode '{}': use \"wait\" or \"reject\"", mode),
+            WhatsAppError::EditRejected(e) => write!(
+                f,
+                "WhatsApp rejected the edit (messages can only be edited for about 15 minutes): {}",
+                e
+            ),
+            WhatsAppError::DownloadFailed(e) => write!(f, "Failed to download: {}", e),
+            WhatsAppError::StoreFailed(e) => write!(f, "Failed to read local store: {}", e),
+            WhatsAppError::UploadFailed(e) => write!(f, "Failed to upload: {}", e),
+            WhatsAppError::MediaRejected(e) => write!(f, "WhatsApp rejected the media upload: {}", e),
+            WhatsAppError::SendFailed(e) => write!(f, "Failed to send: {}", e),
+            WhatsAppError::NotConnected(e) => write!(f, "Not connected to WhatsApp: {}", e),
+            WhatsAppError::ServerRateLimited(e) => write!(f, "WhatsApp is rate limiting this account, try again later: {}", e),
+            WhatsAppError::InvalidRecipient(e) => write!(f, "WhatsApp rejected the recipient: {}", e),
+            WhatsAppError::QueryFailed(e) => write!(f, "Failed to query WhatsApp: {}", e),
+            WhatsAppError::NotGroupMember(jid) => write!(f, "This account is not a member of group {}", jid),
+            WhatsAppError::InvalidGroup(e) => write!(f, "Invalid group: {}", e),
+            WhatsAppError::NotGroupAdmin(jid) => write!(f, "This account is not an admin of group {}", jid),
+            WhatsAppError::ViewOnceUnsupported(kind) => write!(f, "View once only works for images and videos, not {}", kind),
+            WhatsAppError::GifPlaybackUnsupported(kind) => write!(f, "GIF playback only works for videos, not {}", kind),
+            WhatsAppError::InvalidReuseToken(e) => write!(f, "Invalid reuse token: {}", e),
+            WhatsAppError::DatabaseLocked(path) => write!(f, "Couldn't delete {}: the database is still in use. Close any other copy of the app and try again", path),
+            WhatsAppError::ReuseTokenExpired => write!(f, "Reuse token has expired; send the file again to get a new one"),
+            WhatsAppError::LogoutFailed(e) => write!(f, "Failed to logout: {}", e),
+            WhatsAppError::Timeout(secs) => write!(f, "No reply from WhatsApp after {} seconds", secs),
+            WhatsAppError::BusyTryAgain => write!(f, "Too many requests in flight. Please try again shortly."),
+            WhatsAppError::RateLimited { retry_after_ms } => write!(f, "Send rate limit reached, retry in {} ms", retry_after_ms),
+            WhatsAppError::QueueFull => write!(f, "Dropped from the offline queue to make room for newer messages"),
+            WhatsAppError::Cancelled(id) => write!(f, "Upload {} was cancelled", id),
+            WhatsAppError::BotTaskGone => write!(f, "Bot task is no longer running"),
+        }
+    }
+}
+
+impl Serialize for WhatsAppError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            WhatsAppError::MediaTooLarge { size, limit } => {
+                map.serialize_entry("size", size)?;
+                map.serialize_entry("limit", limit)?;
+            }
+            WhatsAppError::FileNotFound { path } | WhatsAppError::FileNotReadable { path, .. } => {
+                map.serialize_entry("path", path)?;
+            }
+            WhatsAppError::RateLimited { retry_after_ms } => {
+                map.serialize_entry("retry_after_ms", retry_after_ms)?;
+            }
+            _ => {}
+        }
+        map.end()
+    }
+}
+
+// Settings file in the app data dir, next to the session databases
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+// Database file used when init_whatsapp isn't given one for the default account
+const DEFAULT_DB_NAME: &str = "whatsapp.db";
+const DEFAULT_ACCOUNT_ID: &str = "default";
+
+// Seconds after the last QR code before the UI is told pairing timed out
+const DEFAULT_QR_TIMEOUT_SECS: u64 = 60;
+
+// How long after the first QR or pairing code to wait for pairing before tearing the
+// bot task down, so the UI isn't left waiting for a scan that never comes
+const INIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+// How long a reuse token from send_media_message stays valid; WhatsApp eventually
+// deletes uploaded media from its servers, after which the token points at nothing
+const REUSE_TOKEN_TTL: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+
+// How long app exit waits for each bot task to disconnect, then for it to drop its store
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+const SHUTDOWN_DRAIN: std::time::Duration = std::time::Duration::from_millis(250);
+
+// How long reset_session waits for a stopped bot task to close its database, then how
+// often it retries deleting a file Windows still reports as in use
+const STORE_RELEASE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+const DB_DELETE_ATTEMPTS: u32 = 5;
+const DB_DELETE_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(200);
+
+// How long a command waits for an idle session to reconnect before giving up
+const IDLE_WAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// How long ping_bot waits for the bot task before calling it stuck
+const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+// Commands queued for the bot task before new ones are refused with BusyTryAgain
+const COMMAND_CHANNEL_CAPACITY: usize = 32;
+
+// How long a command may wait for the bot task before giving up; media transfers get longer
+const DEFAULT_COMMAND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+const DEFAULT_MEDIA_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+// Events set_event_filter can't turn off, so a filter never hides pairing or connection loss
+const ALWAYS_EMITTED_EVENTS: &[&str] = &[
+    "qr-code",
+    "pairing-code",
+    "auth-success",
+    "logged-out",
+    "connection-state",
+    "session-corrupt",
+    "session-replaced",
+    "init-timeout",
+];
+
+// Mirrors log lines to the "debug-log" event; off by default, toggled by set_debug_logging.
+// Global rather than on WhatsAppState so log_event works anywhere a window is at hand.
+static DEBUG_LOGGING: AtomicBool = AtomicBool::new(false);
+
+// Mirrors every protocol event to "raw-event" as its Debug text; off by default because
+// it serializes each event and sends it over IPC. Toggled by enable_raw_events.
+static RAW_EVENTS: AtomicBool = AtomicBool::new(false);
+
+// Seconds without commands before a bot task disconnects to save network use; 0 (the
+// default) never does. Set by set_idle_timeout, shared by every account.
+static IDLE_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+
+// Reconnection attempts before giving up when init_whatsapp doesn't specify
+const DEFAULT_MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+// WhatsApp only allows "Delete for everyone" for roughly two days after sending
+const REVOKE_WINDOW_MS: i64 = 48 * 60 * 60 * 1000;
+
+const MB: u64 = 1024 * 1024;
+
+// Length limits (in characters) WhatsApp enforces on the profile name and "About" text
+const MAX_PROFILE_NAME_CHARS: usize = 25;
+const MAX_STATUS_TEXT_CHARS: usize = 139;
+
+// Background for text statuses when post_status isn't given a colour (opaque dark teal)
+const DEFAULT_STATUS_BACKGROUND: u32 = 0xFF07_5E54;
+
+// Longest group subject and description WhatsApp accepts, in characters
+const MAX_GROUP_SUBJECT_CHARS: usize = 100;
+const MAX_GROUP_DESCRIPTION_CHARS: usize = 2048;
+
+// Disappearing message timers WhatsApp offers: off, 24 hours, 7 days and 90 days
+const DISAPPEARING_TIMER_SECS: &[u32] = &[0, 86_400, 604_800, 7_776_000];
+
+// WhatsApp's longest live location option is 8 hours
+const MAX_LIVE_LOCATION_SECS: u32 = 8 * 60 * 60;
+
+// Longest side in pixels of thumbnails generated for images sent without one
+const THUMBNAIL_SIZE: u32 = 96;
+
+// Number of received media references kept around for download_media
+const MAX_CACHED_MEDIA: usize = 200;
+
+// Number of recent messages (sent or received) whose full content is kept for forward_message
+const MAX_FORWARDABLE_MESSAGES: usize = 200;
+
+// Messages kept per chat for get_messages, in memory and in the history file
+const MAX_HISTORY_PER_CHAT: usize = 1000;
+
+// Incoming message ids remembered to drop replays after a reconnect
+const MAX_SEEN_MESSAGE_IDS: usize = 1000;
+
+// How long a fetched profile picture URL is reused before asking again
+const PROFILE_PICTURE_CACHE_MS: i64 = 60 * 60 * 1000;
+
+// Messages held by queue_if_offline; the oldest is dropped once this is reached
+const MAX_PENDING_MESSAGES: usize = 100;
+
+// Incoming messages held for one "messages-batch" during an offline sync; a bigger
+// backlog goes out in several batches of this size
+const MAX_OFFLINE_BATCH: usize = 500;
+
+// Source of local ids for queued messages, which have no WhatsApp id until sent
+static NEXT_QUEUED_ID: AtomicU64 = AtomicU64::new(1);
+
+// Source of live location generations, telling a share apart from the one that replaced it
+static NEXT_LIVE_LOCATION_ID: AtomicU64 = AtomicU64::new(1);
+
+// How long a "not on WhatsApp" answer is reused before asking the server again
+const NEGATIVE_NUMBER_CACHE_MS: i64 = 10 * 60 * 1000;
+
+// What the bot task did with a text or media send
+#[derive(Debug, PartialEq)]
+enum Delivery<T> {
+    Sent(T),
+    // Held back by dry run: the Debug text of the message that would have gone out
+    DryRun(String),
+}
+
+impl Delivery<String> {
+    // The message id, for commands that return nothing else; a dry run has none
+    fn into_message_id(self) -> Option<String> {
+        match self {
+            Delivery::Sent(message_id) => Some(message_id),
+            Delivery::DryRun(_) => None,
+        }
+    }
+}
 
 // Commands sent to the bot task to avoid cross-thread Rc issues
 enum BotCommand {
     SendMessage {
         jid: Jid,
         message: wa::Message,
-        reply: oneshot::Sender<Result<String, String>>,
+        context: Option<wa::ContextInfo>,
+        retries: u32,
+        reply: oneshot::Sender<Result<Delivery<String>, WhatsAppError>>,
     },
     SendMediaMessage {
         jid: Jid,
@@ -28,370 +408,6111 @@ enum BotCommand {
         mime_type: String,
         caption: String,
         file_name: String,
-        reply: oneshot::Sender<Result<String, String>>,
+        ptt: bool,
+        view_once: bool,
+        gif_playback: bool,
+        context: Option<wa::ContextInfo>,
+        jpeg_thumbnail: Option<Vec<u8>>,
+        upload_id: String,
+        cancel: CancellationToken,
+        window: Window,
+        reply: oneshot::Sender<Result<Delivery<(String, UploadedMedia)>, WhatsAppError>>,
+    },
+    PostStatus {
+        content: StatusContent,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SendSticker {
+        jid: Jid,
+        media_data: Vec<u8>,
+        is_animated: bool,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SendContact {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
     },
+    SendPoll {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SendLocation {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SendButtons {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    ForwardMessage {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SetDisappearingMessages {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SendReaction {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    SetPresence {
+        jid: Jid,
+        typing: bool,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    SetGlobalPresence {
+        online: bool,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    SubscribePresence {
+        jid: Jid,
+        subscribe: bool,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    SetProfileName {
+        name: String,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    SetStatusText {
+        status: String,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    RevokeMessage {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    EditMessage {
+        jid: Jid,
+        message: wa::Message,
+        reply: oneshot::Sender<Result<String, WhatsAppError>>,
+    },
+    MarkRead {
+        jid: Jid,
+        message_ids: Vec<String>,
+        reply: oneshot::Sender<Result<usize, WhatsAppError>>,
+    },
+    DownloadMedia {
+        media: CachedMedia,
+        reply: oneshot::Sender<Result<Vec<u8>, WhatsAppError>>,
+    },
+    GetContacts {
+        reply: oneshot::Sender<Result<Vec<ContactInfo>, WhatsAppError>>,
+    },
+    Resync {
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    GetChats {
+        reply: oneshot::Sender<Result<Vec<ChatSummary>, WhatsAppError>>,
+    },
+    CheckNumber {
+        phone: String,
+        reply: oneshot::Sender<Result<Option<Jid>, WhatsAppError>>,
+    },
+    GetGroupInfo {
+        jid: Jid,
+        reply: oneshot::Sender<Result<GroupInfo, WhatsAppError>>,
+    },
+    CreateGroup {
+        subject: String,
+        participants: Vec<Jid>,
+        reply: oneshot::Sender<Result<Jid, WhatsAppError>>,
+    },
+    UpdateGroupParticipants {
+        jid: Jid,
+        action: ParticipantAction,
+        participants: Vec<Jid>,
+        reply: oneshot::Sender<Result<Vec<ParticipantResult>, WhatsAppError>>,
+    },
+    SetGroupSubject {
+        jid: Jid,
+        subject: String,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    SetGroupDescription {
+        jid: Jid,
+        description: String,
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    GetProfilePicture {
+        jid: Jid,
+        reply: oneshot::Sender<Result<Option<String>, WhatsAppError>>,
+    },
+    GetBusinessProfile {
+        jid: Jid,
+        reply: oneshot::Sender<Result<Option<BusinessProfile>, WhatsAppError>>,
+    },
+    Ping {
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    // Reconnects a bot task that disconnected for inactivity
+    Wake,
+    Logout {
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+    Shutdown {
+        reply: oneshot::Sender<Result<(), WhatsAppError>>,
+    },
+}
+
+impl BotCommand {
+    // Commands that upload before they send, which the bot task runs in its media lane
+    fn uploads_media(&self) -> bool {
+        matches!(
+            self,
+            BotCommand::SendMediaMessage { .. }
+                | BotCommand::SendSticker { .. }
+                | BotCommand::PostStatus { content: StatusContent::Media { .. }, .. }
+        )
+    }
+}
+
+// A media lane command being processed; it borrows the bot task's client
+type LaneFuture<'a> = std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + 'a>>;
+
+// Upload-bearing commands, run one at a time beside the bot task's command loop instead
+// of inside it, so text sends queued behind a 100MB upload go out while it's still
+// uploading. The futures are polled from the bot task itself; the client never leaves it.
+// A text sent after a media message can therefore arrive before it.
+#[derive(Default)]
+struct MediaLane<'a> {
+    queue: VecDeque<BotCommand>,
+    in_flight: Option<LaneFuture<'a>>,
+}
+
+impl<'a> MediaLane<'a> {
+    // Takes the command if it belongs in the lane; anything else is handed back to run inline
+    fn offer(&mut self, command: BotCommand) -> Option<BotCommand> {
+        if command.uploads_media() {
+            self.queue.push_back(command);
+            None
+        } else {
+            Some(command)
+        }
+    }
+
+    // Starts the next queued command unless one is already running
+    fn start_next(&mut self, run: impl FnOnce(BotCommand) -> LaneFuture<'a>) {
+        if self.in_flight.is_none() {
+            if let Some(command) = self.queue.pop_front() {
+                self.in_flight = Some(run(command));
+            }
+        }
+    }
+
+    // Finishes when the running command does; never while nothing is running
+    async fn finished(&mut self) {
+        match self.in_flight.as_mut() {
+            Some(command) => {
+                command.await;
+                self.in_flight = None;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.in_flight.is_none() && self.queue.is_empty()
+    }
+}
+
+// Everything needed to fetch and decrypt a received attachment later
+#[derive(Clone)]
+struct CachedMedia {
+    direct_path: String,
+    media_key: Vec<u8>,
+    file_sha256: Vec<u8>,
+    file_enc_sha256: Vec<u8>,
+    file_length: u64,
+    media_type: MediaType,
+}
+
+// Map holding at most `capacity` entries, evicting the oldest inserted first
+struct BoundedMap<K, V> {
+    entries: HashMap<K, V>,
+    order: VecDeque<K>,
+    capacity: usize,
+}
+
+impl<K: Clone + Eq + std::hash::Hash, V> BoundedMap<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // Returns false if the key was already present; its value is replaced but it
+    // keeps its place in the eviction order
+    fn insert(&mut self, key: K, value: V) -> bool {
+        if self.entries.insert(key.clone(), value).is_some() {
+            return false;
+        }
+        self.order.push_back(key);
+        self.retain_last(self.capacity);
+        true
+    }
+
+    fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Eq + std::hash::Hash + ?Sized,
+    {
+        self.entries.get(key)
+    }
+
+    fn retain_last(&mut self, keep: usize) {
+        while self.order.len() > keep {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Recent media references keyed by message id
+type MediaCache = BoundedMap<String, CachedMedia>;
+
+// Full content of recent messages keyed by id, so they can be forwarded without
+// re-uploading their media
+type ForwardableMessages = BoundedMap<String, wa::Message>;
+
+// Recently seen incoming message ids
+type SeenMessages = BoundedMap<String, ()>;
+
+// A message in a chat's history, as returned by get_messages
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredMessage {
+    id: String,
+    chat_jid: String,
+    sender: String,
+    from_me: bool,
+    text: Option<String>, // Text, caption or a "[image]"-style placeholder
+    timestamp: i64,
+}
+
+// Per-chat message history, oldest first, mirrored to an append-only JSON lines file
+// next to the session database so it survives restarts
+#[derive(Default)]
+struct MessageHistory {
+    chats: HashMap<String, Vec<StoredMessage>>,
+    path: Option<std::path::PathBuf>,
+}
+
+impl MessageHistory {
+    // Loads a history file, trimming each chat to the cap and compacting the file if needed
+    fn load(window: &Window, path: std::path::PathBuf) -> Self {
+        let mut history = MessageHistory::default();
+        let mut trimmed = false;
+        
+        if let Ok(contents) = std::fs::read_to_string(&path) {
+            for line in contents.lines() {
+                match serde_json::from_str::<StoredMessage>(line) {
+                    Ok(message) => trimmed |= history.insert(message),
+                    Err(e) => {
+                        log_event(window, "warn", format!("Skipping unreadable history line: {}", e));
+                        trimmed = true;
+                    }
+                }
+            }
+        }
+        
+        if trimmed {
+            let lines: Vec<String> = history
+                .chats
+                .values()
+                .flatten()
+                .filter_map(|message| serde_json::to_string(message).ok())
+                .collect();
+            if let Err(e) = std::fs::write(&path, lines.join("\n") + "\n") {
+                log_event(window, "error", format!("Failed to compact message history: {}", e));
+            }
+        }
+        
+        history.path = Some(path);
+        history
+    }
+
+    // Adds a message in timestamp order and persists it; duplicates are ignored
+    fn push(&mut self, window: &Window, message: StoredMessage) {
+        let line = serde_json::to_string(&message);
+        let is_new = self
+            .chats
+            .get(&message.chat_jid)
+            .is_none_or(|messages| !messages.iter().any(|m| m.id == message.id));
+        if !is_new {
+            return;
+        }
+        self.insert(message);
+        
+        if let (Some(path), Ok(line)) = (&self.path, line) {
+            use std::io::Write;
+            let written = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+            if let Err(e) = written {
+                log_event(window, "error", format!("Failed to persist message history: {}", e));
+            }
+        }
+    }
+
+    // Returns true if the chat went over the cap and its oldest message was dropped
+    fn insert(&mut self, message: StoredMessage) -> bool {
+        let messages = self.chats.entry(message.chat_jid.clone()).or_default();
+        let position = messages.partition_point(|m| m.timestamp <= message.timestamp);
+        messages.insert(position, message);
+        if messages.len() > MAX_HISTORY_PER_CHAT {
+            messages.remove(0);
+            return true;
+        }
+        false
+    }
+}
+
+// Serializable contact entry for frontend
+#[derive(Clone, Serialize)]
+pub struct ContactInfo {
+    jid: String,
+    name: Option<String>,
+}
+
+// Outcome of a text send, so callers can see where the message actually went
+#[derive(Clone, Serialize)]
+pub struct SendResult {
+    message_id: Option<String>, // Local tracking id while queued; None for a dry run
+    jid: String,
+    normalized_contact: String,
+    queued: bool,
+    dry_run: Option<String>, // Debug text of the message a dry run built instead
+}
+
+// Outcome of a media send; the upload fields let the same file be sent again without
+// uploading it, either directly or through reuse_token and send_cached_media.
+// A dry run uploads and sends nothing, so it has only dry_run.
+#[derive(Clone, Serialize)]
+pub struct MediaSendResult {
+    message_id: Option<String>,
+    #[serde(flatten)]
+    upload: Option<MediaUploadResult>,
+    reuse_token: Option<String>,
+    dry_run: Option<String>, // Debug text of the message a dry run built instead
+}
+
+// Where a sent file lives on WhatsApp's servers and how to decrypt it
+#[derive(Clone, Serialize)]
+pub struct MediaUploadResult {
+    url: String,
+    direct_path: String,
+    media_key: String, // Base64
+    file_sha256: String, // Base64
+    file_enc_sha256: String, // Base64
+    file_length: u64,
+}
+
+// Group header details and member list for frontend
+#[derive(Clone, Serialize)]
+pub struct GroupInfo {
+    subject: String,
+    participants: Vec<String>,
+    owner: Option<String>,
+    description: Option<String>,
+}
+
+// Outcome for one member of an update_group_participants call; adding someone can fail
+// on its own, e.g. when their privacy settings only let contacts add them
+#[derive(Clone, Serialize)]
+pub struct ParticipantResult {
+    participant: String,
+    success: bool,
+    error: Option<String>,
+}
+
+// Public details of a business account, for contact info panels
+#[derive(Clone, Serialize)]
+pub struct BusinessProfile {
+    description: Option<String>,
+    email: Option<String>,
+    website: Option<String>,
+    category: Option<String>,
+    address: Option<String>,
+}
+
+// Whether a phone number is registered, with the JID WhatsApp resolved it to
+#[derive(Clone, Serialize)]
+pub struct NumberCheck {
+    exists: bool,
+    jid: Option<String>,
+}
+
+// Proxy for init_whatsapp, e.g. { url: "http://proxy.corp:8080", username, password }
+#[derive(Clone, Deserialize)]
+pub struct ProxyConfig {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl ProxyConfig {
+    // Proxy URL with any credentials embedded, the form the HTTP client reads
+    fn to_url(&self) -> Result<String, WhatsAppError> {
+        let url = self.url.trim();
+        let invalid = || WhatsAppError::InvalidProxy(url.to_string());
+        let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+        let scheme = scheme.to_ascii_lowercase();
+        let host = rest.trim_end_matches('/');
+        if !matches!(scheme.as_str(), "http" | "https" | "socks5")
+            || host.is_empty()
+            || host.contains(|c: char| c == '/' || c == '@' || c.is_whitespace())
+        {
+            return Err(invalid());
+        }
+        
+        match self.username.as_deref().filter(|user| !user.is_empty()) {
+            Some(user) => {
+                let password = self.password.as_deref().unwrap_or("");
+                Ok(format!("{}://{}:{}@{}", scheme, percent_encode(user), percent_encode(password), host))
+            }
+            None => Ok(format!("{}://{}", scheme, host)),
+        }
+    }
+
+    // HTTP client for the bot that sends everything through this proxy
+    fn http_client(&self) -> Result<ProxiedHttpClient, WhatsAppError> {
+        let url = self.to_url()?;
+        let proxy = ureq::Proxy::new(&url)
+            .map_err(|_| WhatsAppError::InvalidProxy(self.url.trim().to_string()))?;
+        let agent = ureq::Agent::config_builder()
+            .proxy(Some(proxy))
+            // The bot reads error statuses itself, as it does with UreqHttpClient
+            .http_status_as_error(false)
+            .build()
+            .into();
+        Ok(ProxiedHttpClient { agent })
+    }
+}
+
+// The same requests UreqHttpClient makes, through a ureq agent of its own. UreqHttpClient
+// uses ureq's default agent, which only finds a proxy in the process environment, so a
+// per-account proxy needs its own agent. It only covers HTTP (media transfers): the websocket transport
+// has no proxy support and still connects directly.
+struct ProxiedHttpClient {
+    agent: ureq::Agent,
+}
+
+#[async_trait::async_trait]
+impl HttpClient for ProxiedHttpClient {
+    async fn execute(&self, request: HttpRequest) -> anyhow::Result<HttpResponse> {
+        let agent = self.agent.clone();
+        // ureq blocks, so keep it off the async workers
+        tokio::task::spawn_blocking(move || {
+            let response = if request.method.eq_ignore_ascii_case("POST") {
+                let mut post = agent.post(&request.url);
+                for (name, value) in &request.headers {
+                    post = post.header(name, value);
+                }
+                post.send(request.body.as_deref().unwrap_or_default())?
+            } else {
+                let mut get = agent.get(&request.url);
+                for (name, value) in &request.headers {
+                    get = get.header(name, value);
+                }
+                get.call()?
+            };
+            let status_code = response.status().as_u16();
+            let body = response.into_body().read_to_vec()?;
+            Ok(HttpResponse { status_code, body })
+        })
+        .await?
+    }
+}
+
+// The live location an account is sharing; update_live_location sends to it until it expires
+struct LiveLocation {
+    jid: Jid,
+    started_at_ms: i64,
+    expires_at_ms: i64,
+    sequence: i64,
+    generation: u64, // From NEXT_LIVE_LOCATION_ID
+    stop: CancellationToken,
+}
+
+// A quick-reply button for send_buttons; the id comes back in "button-reply"
+#[derive(Clone, Deserialize)]
+pub struct ButtonDef {
+    id: String,
+    text: String,
+}
+
+// Per-type upload size limits in bytes, checked before media is read into memory
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MediaLimits {
+    pub image: u64,
+    pub video: u64,
+    pub audio: u64,
+    pub document: u64,
+}
+
+impl Default for MediaLimits {
+    fn default() -> Self {
+        Self {
+            image: 5 * MB,
+            video: 16 * MB,
+            audio: 16 * MB,
+            document: 100 * MB,
+        }
+    }
+}
+
+impl MediaLimits {
+    fn check(&self, media_category: &str, size: u64) -> Result<(), WhatsAppError> {
+        let limit = match media_category {
+            "image" => self.image,
+            "video" => self.video,
+            "audio" => self.audio,
+            _ => self.document,
+        };
+        if size > limit {
+            return Err(WhatsAppError::MediaTooLarge { size, limit });
+        }
+        Ok(())
+    }
+}
+
+// What a text send does when the rate limit is reached
+#[derive(Clone, Copy, PartialEq)]
+enum RateLimitMode {
+    Wait,
+    Reject,
+}
+
+impl RateLimitMode {
+    fn parse(mode: &str) -> Result<Self, WhatsAppError> {
+        match mode {
+            "wait" => Ok(RateLimitMode::Wait),
+            "reject" => Ok(RateLimitMode::Reject),
+            _ => Err(WhatsAppError::InvalidRateLimit(mode.to_string())),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            RateLimitMode::Wait => "wait",
+            RateLimitMode::Reject => "reject",
+        }
+    }
+}
+
+// Preferences set through commands, saved to SETTINGS_FILE_NAME so they survive a relaunch
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    event_filter: Option<Vec<String>>,
+    debug_logging: bool,
+    raw_events: bool,
+    idle_timeout_minutes: u32,
+    rate_limit: Option<RateLimitSettings>,
+    media_limits: Option<MediaLimits>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RateLimitSettings {
+    per_minute: u32,
+    mode: String,
+}
+
+// Change update_group_participants makes to each listed member
+#[derive(Clone, Copy)]
+enum ParticipantAction {
+    Add,
+    Remove,
+    Promote,
+    Demote,
+}
+
+impl ParticipantAction {
+    fn parse(action: &str) -> Result<Self, WhatsAppError> {
+        match action {
+            "add" => Ok(ParticipantAction::Add),
+            "remove" => Ok(ParticipantAction::Remove),
+            "promote" => Ok(ParticipantAction::Promote),
+            "demote" => Ok(ParticipantAction::Demote),
+            _ => Err(WhatsAppError::InvalidGroup(format!(
+                "unknown participant action '{}', expected add, remove, promote or demote",
+                action
+            ))),
+        }
+    }
+}
+
+// Token bucket shared by every account, set up by configure_rate_limit. The bucket holds
+// ten seconds' worth of sends, so a burst can't spend a whole minute's budget at once.
+struct RateLimiter {
+    per_minute: u32,
+    mode: RateLimitMode,
+    tokens: f64,
+    last_refill: std::time::Instant,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32, mode: RateLimitMode) -> Self {
+        let mut limiter = Self {
+            per_minute,
+            mode,
+            tokens: 0.0,
+            last_refill: std::time::Instant::now(),
+        };
+        limiter.tokens = limiter.capacity();
+        limiter
+    }
+
+    fn capacity(&self) -> f64 {
+        (self.per_minute as f64 / 6.0).max(1.0)
+    }
+
+    // Takes a token, or says how long until the next one is available
+    fn try_acquire(&mut self) -> Result<(), std::time::Duration> {
+        let now = std::time::Instant::now();
+        let per_second = self.per_minute as f64 / 60.0;
+        let refilled = now.duration_since(self.last_refill).as_secs_f64() * per_second;
+        self.tokens = (self.tokens + refilled).min(self.capacity());
+        self.last_refill = now;
+        
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(std::time::Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+// Where an uploaded file lives on WhatsApp's servers and how to decrypt it
+#[derive(Clone, Serialize, Deserialize)]
+struct UploadedMedia {
+    url: String,
+    direct_path: String,
+    media_key: Vec<u8>,
+    file_sha256: Vec<u8>,
+    file_enc_sha256: Vec<u8>,
+    file_length: u64,
+}
+
+// Everything send_cached_media needs to rebuild a media message, sealed by reuse_token
+#[derive(Serialize, Deserialize)]
+struct ReuseToken {
+    media_category: String,
+    mime_type: String,
+    file_name: String,
+    jpeg_thumbnail: Option<Vec<u8>>,
+    upload: UploadedMedia,
+    created_at: i64, // Unix millis
+}
+
+impl ReuseToken {
+    // Base64 JSON followed by a base64 SHA-256 of it. The checksum catches a truncated
+    // or edited token; it isn't a signature, since the token never leaves this app.
+    fn encode(&self) -> String {
+        let payload = serde_json::to_vec(self).unwrap_or_default();
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        format!("{}.{}", engine.encode(&payload), engine.encode(Sha256::digest(&payload)))
+    }
+
+    fn decode(token: &str) -> Result<Self, WhatsAppError> {
+        let engine = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        let invalid = |reason: &str| WhatsAppError::InvalidReuseToken(reason.to_string());
+        let (payload, checksum) = token.trim().split_once('.').ok_or_else(|| invalid("missing checksum"))?;
+        let payload = engine.decode(payload).map_err(|_| invalid("not base64"))?;
+        let checksum = engine.decode(checksum).map_err(|_| invalid("not base64"))?;
+        if Sha256::digest(&payload).as_slice() != checksum.as_slice() {
+            return Err(invalid("checksum mismatch"));
+        }
+        let token: ReuseToken = serde_json::from_slice(&payload).map_err(|e| invalid(&e.to_string()))?;
+        if now_millis().saturating_sub(token.created_at) > REUSE_TOKEN_TTL.as_millis() as i64 {
+            return Err(WhatsAppError::ReuseTokenExpired);
+        }
+        Ok(token)
+    }
+}
+
+// What post_status shares: coloured text, or an image or video uploaded by the bot task
+enum StatusContent {
+    Text {
+        text: String,
+        background_argb: u32,
+    },
+    Media {
+        data: Vec<u8>,
+        media_type_enum: MediaType,
+        media_category: String, // "image" or "video"
+        mime_type: String,
+        caption: String,
+        jpeg_thumbnail: Option<Vec<u8>>,
+    },
+}
+
+// Media attachment collected by a command, before the MIME type is resolved
+struct OutgoingMedia {
+    data: Vec<u8>,
+    media_category: String,
+    file_name: String,
+    caption: String,
+    ptt: bool,
+    view_once: bool,
+    gif_playback: bool, // Video loops silently like a GIF
+    context: Option<wa::ContextInfo>,
+    jpeg_thumbnail: Option<Vec<u8>>,
+    upload_id: Option<String>,
+    mime_override: Option<String>, // Used instead of the guess from the file extension
+}
+
+// Delivery state of an outbound message, ordered so statuses only move forward
+#[derive(Clone, Copy, PartialEq, PartialOrd, Serialize)]
+pub enum MessageStatus {
+    Sent,
+    Delivered,
+    Read,
+}
+
+// Latest activity in a chat, tracked as messages arrive and go out
+#[derive(Clone)]
+struct ChatActivity {
+    last_message_preview: Option<String>,
+    last_timestamp: i64,
+    unread_count: u32,
+}
+
+// Chat list entry for frontend
+#[derive(Clone, Serialize)]
+pub struct ChatSummary {
+    jid: String,
+    name: Option<String>,
+    last_message_preview: Option<String>,
+    last_timestamp: i64,
+    unread_count: u32,
+}
+
+// Outbound message produced by this client, kept for revoking and editing
+struct SentMessage {
+    jid: Jid,
+    sent_at_ms: i64,
+}
+
+// Connection lifecycle as reported to the frontend
+#[derive(Clone, Copy, PartialEq, Serialize)]
+pub enum ConnectionStatus {
+    Uninitialized,
+    WaitingForQr,
+    Authenticated,
+    Connected,
+    LoggedOut,
+}
+
+// Everything tied to one WhatsApp account: its bot task channel, connection flags and caches
+pub struct AccountSession {
+    account_id: String,
+    command_tx: Arc<Mutex<Option<mpsc::Sender<BotCommand>>>>,
+    command_timeout: std::time::Duration,
+    event_filter: Arc<std::sync::RwLock<Option<HashSet<String>>>>,
+    rate_limiter: Arc<Mutex<Option<RateLimiter>>>,
+    dry_run: Arc<AtomicBool>, // Shared with WhatsAppState
+    is_initializing: Arc<Mutex<bool>>,
+    was_replaced: Arc<Mutex<bool>>,
+    is_authenticated: Arc<Mutex<bool>>,
+    is_ready: Arc<Mutex<bool>>,
+    connected: Arc<Notify>, // Signalled each time Event::Connected sets is_ready
+    is_idle: Arc<Mutex<bool>>, // Disconnected by the idle timeout; the next command reconnects
+    status: Arc<Mutex<ConnectionStatus>>,
+    self_jid: Arc<Mutex<Option<String>>>,
+    current_qr: Arc<Mutex<Option<String>>>,
+    media_cache: Arc<Mutex<MediaCache>>,
+    forwardable: Arc<Mutex<ForwardableMessages>>,
+    seen_message_ids: Arc<Mutex<SeenMessages>>,
+    message_statuses: Arc<Mutex<HashMap<String, MessageStatus>>>,
+    sent_messages: Arc<Mutex<HashMap<String, SentMessage>>>,
+    chats: Arc<Mutex<HashMap<String, ChatActivity>>>,
+    history: Arc<Mutex<MessageHistory>>,
+    unregistered_numbers: Arc<Mutex<HashMap<String, i64>>>,
+    profile_pictures: Arc<Mutex<HashMap<String, (Option<String>, i64)>>>,
+    disappearing_timers: Arc<Mutex<HashMap<String, u32>>>,
+    contact_names: Arc<Mutex<HashMap<String, String>>>, // Bare JID -> pushname seen on the wire
+    offline_batch: Arc<Mutex<Option<Vec<IncomingMessageEvent>>>>, // Some while an offline sync runs
+    pending: Arc<Mutex<VecDeque<BotCommand>>>,
+    db_path: Arc<Mutex<Option<std::path::PathBuf>>>,
+    // Dangles once the bot task has dropped the store and SQLite has closed the file
+    store: Arc<Mutex<std::sync::Weak<SqliteStore>>>,
+}
+
+impl AccountSession {
+    fn new(
+        account_id: &str,
+        command_timeout: std::time::Duration,
+        event_filter: Arc<std::sync::RwLock<Option<HashSet<String>>>>,
+        rate_limiter: Arc<Mutex<Option<RateLimiter>>>,
+        dry_run: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            command_tx: Arc::new(Mutex::new(None)),
+            command_timeout,
+            event_filter,
+            rate_limiter,
+            dry_run,
+            is_initializing: Arc::new(Mutex::new(false)),
+            was_replaced: Arc::new(Mutex::new(false)),
+            is_authenticated: Arc::new(Mutex::new(false)),
+            is_ready: Arc::new(Mutex::new(false)),
+            connected: Arc::new(Notify::new()),
+            is_idle: Arc::new(Mutex::new(false)),
+            status: Arc::new(Mutex::new(ConnectionStatus::Uninitialized)),
+            self_jid: Arc::new(Mutex::new(None)),
+            current_qr: Arc::new(Mutex::new(None)),
+            media_cache: Arc::new(Mutex::new(MediaCache::new(MAX_CACHED_MEDIA))),
+            forwardable: Arc::new(Mutex::new(ForwardableMessages::new(MAX_FORWARDABLE_MESSAGES))),
+            seen_message_ids: Arc::new(Mutex::new(SeenMessages::new(MAX_SEEN_MESSAGE_IDS))),
+            message_statuses: Arc::new(Mutex::new(HashMap::new())),
+            sent_messages: Arc::new(Mutex::new(HashMap::new())),
+            chats: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(Mutex::new(MessageHistory::default())),
+            unregistered_numbers: Arc::new(Mutex::new(HashMap::new())),
+            profile_pictures: Arc::new(Mutex::new(HashMap::new())),
+            disappearing_timers: Arc::new(Mutex::new(HashMap::new())),
+            contact_names: Arc::new(Mutex::new(HashMap::new())),
+            offline_batch: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            db_path: Arc::new(Mutex::new(None)),
+            store: Arc::new(Mutex::new(std::sync::Weak::new())),
+        }
+    }
+
+    // Window event name namespaced to this account, e.g. "qr-code::work"
+    fn event(&self, name: &str) -> String {
+        format!("{}::{}", name, self.account_id)
+    }
+
+    // Emit an account event to the frontend unless set_event_filter has excluded it
+    fn emit<S: Serialize + Clone>(&self, window: &Window, name: &str, payload: S) {
+        let allowed = match &*self.event_filter.read().unwrap_or_else(|e| e.into_inner()) {
+            Some(filter) => filter.contains(name) || ALWAYS_EMITTED_EVENTS.contains(&name),
+            None => true,
+        };
+        if allowed {
+            let _ = window.emit(&self.event(name), payload);
+        }
+    }
+
+    // Remember an outbound message so its status can be tracked and it can be revoked later
+    async fn record_sent(&self, window: &Window, message_id: &str, jid: &Jid, preview: &str) {
+        let timestamp = now_millis() / 1000;
+        self.record_chat_activity(&bare_jid(jid), Some(preview.to_string()), timestamp, false).await;
+        let sender = self.self_jid.lock().await.clone().unwrap_or_default();
+        self.history.lock().await.push(window, StoredMessage {
+            id: message_id.to_string(),
+            chat_jid: bare_jid(jid),
+            sender,
+            from_me: true,
+            text: Some(preview.to_string()),
+            timestamp,
+        });
+        self.message_statuses
+            .lock()
+            .await
+            .insert(message_id.to_string(), MessageStatus::Sent);
+        self.sent_messages.lock().await.insert(
+            message_id.to_string(),
+            SentMessage {
+                jid: jid.clone(),
+                sent_at_ms: now_millis(),
+            },
+        );
+    }
+
+    // Update a chat's preview and time; incoming messages also count as unread
+    async fn record_chat_activity(&self, chat_jid: &str, preview: Option<String>, timestamp: i64, incoming: bool) {
+        let mut chats = self.chats.lock().await;
+        let chat = chats.entry(chat_jid.to_string()).or_insert(ChatActivity {
+            last_message_preview: None,
+            last_timestamp: 0,
+            unread_count: 0,
+        });
+        // Replayed offline messages can arrive out of order
+        if timestamp >= chat.last_timestamp {
+            chat.last_message_preview = preview;
+            chat.last_timestamp = timestamp;
+        }
+        if incoming {
+            chat.unread_count += 1;
+        } else {
+            // Replying from here means the chat has been seen
+            chat.unread_count = 0;
+        }
+    }
+
+    // Whether sends should be built and reported instead of reaching WhatsApp
+    fn is_dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::Relaxed)
+    }
+
+    // Stands in for a send in dry run mode: reports the message that would have gone
+    // out as a "dry-run-message" event and returns the same Debug text for the caller
+    fn dry_run_send<T>(&self, window: &Window, jid: &Jid, message: &wa::Message) -> Delivery<T> {
+        log_event(window, "info", format!("Dry run: not sending to {}", jid));
+        let preview = format!("{:#?}", message);
+        self.emit(window, "dry-run-message", DryRunMessageEvent {
+            jid: jid.to_string(),
+            message: preview.clone(),
+        });
+        Delivery::DryRun(preview)
+    }
+
+    async fn ensure_ready(&self) -> Result<(), WhatsAppError> {
+        if *self.is_ready.lock().await {
+            return Ok(());
+        }
+        // Dropped for inactivity rather than lost: reconnect and carry on with the command
+        if *self.is_idle.lock().await {
+            return self.wake().await;
+        }
+        Err(WhatsAppError::NotReady)
+    }
+
+    // Asks an idle bot task to reconnect without waiting for it
+    async fn request_wake(&self) -> Result<(), WhatsAppError> {
+        let tx = self.command_tx.lock().await.clone().ok_or(WhatsAppError::NotReady)?;
+        tx.submit(BotCommand::Wake)
+    }
+
+    async fn wake(&self) -> Result<(), WhatsAppError> {
+        self.request_wake().await?;
+        tokio::time::timeout(IDLE_WAKE_TIMEOUT, self.wait_until_ready()).await
+            .map_err(|_| WhatsAppError::Timeout(IDLE_WAKE_TIMEOUT.as_secs()))
+    }
+
+    // Finishes once Event::Connected has marked the session ready
+    async fn wait_until_ready(&self) {
+        let connected = self.connected.notified();
+        tokio::pin!(connected);
+        // Registered before the check, so a Connected landing in between isn't missed
+        connected.as_mut().enable();
+        if !*self.is_ready.lock().await {
+            connected.await;
+        }
+    }
+
+    // Send a command to the bot task and wait for its reply
+    async fn dispatch<T>(
+        &self,
+        make_command: impl FnOnce(oneshot::Sender<Result<T, WhatsAppError>>) -> BotCommand,
+    ) -> Result<T, WhatsAppError> {
+        self.dispatch_within(self.command_timeout, make_command).await
+    }
+
+    // Reply context for a quoted message, carrying as much of its content as is still cached
+    // so the reply bubble previews it (media with its thumbnail) instead of rendering blank.
+    // With nothing cached the quote carries just the id and sender.
+    async fn quote_context(
+        &self,
+        quoted_message_id: Option<String>,
+        quoted_sender: Option<String>,
+    ) -> Option<wa::ContextInfo> {
+        let mut context = build_quote_context(quoted_message_id, quoted_sender)?;
+        let id = context.stanza_id.clone().unwrap_or_default();
+        if let Some(quoted) = self.quoted_content(&id).await {
+            context.quoted_message = Some(Box::new(quoted));
+        }
+        Some(context)
+    }
+
+    async fn quoted_content(&self, message_id: &str) -> Option<wa::Message> {
+        if let Some(message) = self.forwardable.lock().await.get(message_id) {
+            // Quotes don't nest, so the quoted message's own reply context is dropped
+            let mut quoted = message.clone();
+            if let Some(context) = context_info_slot(&mut quoted) {
+                *context = None;
+            }
+            return Some(quoted);
+        }
+        // Evicted or from before a restart: history only has text, so quote that
+        let history = self.history.lock().await;
+        let text = history
+            .chats
+            .values()
+            .flatten()
+            .find(|m| m.id == message_id)
+            .and_then(|m| m.text.clone())?;
+        Some(wa::Message {
+            conversation: Some(text),
+            ..Default::default()
+        })
+    }
+
+    // Whether a JID is this account's own number, i.e. the "Message yourself" chat
+    async fn is_self(&self, jid: &Jid) -> bool {
+        is_own_jid(self.self_jid.lock().await.as_deref(), jid)
+    }
+
+    // Remembers the display name a contact chose for themselves, emitting contact-updated on change
+    async fn update_contact_name(&self, window: &Window, jid: &Jid, pushname: &str) {
+        let pushname = pushname.trim();
+        if pushname.is_empty() {
+            return;
+        }
+        let jid = bare_jid(jid);
+        let previous = self.contact_names.lock().await.insert(jid.clone(), pushname.to_string());
+        if previous.as_deref() != Some(pushname) {
+            self.emit(window, "contact-updated", ContactUpdatedEvent {
+                jid,
+                pushname: pushname.to_string(),
+            });
+        }
+    }
+
+    // Stops batching incoming messages and sends whatever was held back
+    async fn end_offline_sync(&self, window: &Window) {
+        if let Some(batch) = self.offline_batch.lock().await.take() {
+            if !batch.is_empty() {
+                self.emit(window, "messages-batch", batch);
+            }
+        }
+    }
+
+    // Stamps an outgoing message with the chat's disappearing timer, if it has one;
+    // without it the recipient's client keeps the message forever
+    async fn apply_disappearing_timer(&self, jid: &Jid, message: &mut wa::Message) {
+        let Some(seconds) = self.disappearing_timers.lock().await.get(&bare_jid(jid)).copied() else {
+            return;
+        };
+        if let Some(context) = context_info_slot(message) {
+            context.get_or_insert_with(Default::default).expiration = Some(seconds);
+        }
+    }
+
+    // Waits for, or refuses, a send that would exceed the configured rate limit
+    async fn acquire_send_slot(&self) -> Result<(), WhatsAppError> {
+        self.take_send_slot(false).await
+    }
+
+    // Like acquire_send_slot, but always waits, even in reject mode: for sends nobody is
+    // waiting on to retry, such as the queue flushed on reconnect
+    async fn wait_for_send_slot(&self) {
+        let _ = self.take_send_slot(true).await;
+    }
+
+    async fn take_send_slot(&self, always_wait: bool) -> Result<(), WhatsAppError> {
+        loop {
+            let wait = {
+                let mut guard = self.rate_limiter.lock().await;
+                let Some(limiter) = guard.as_mut() else {
+                    return Ok(());
+                };
+                match limiter.try_acquire() {
+                    Ok(()) => return Ok(()),
+                    Err(wait) if limiter.mode == RateLimitMode::Reject && !always_wait => {
+                        return Err(WhatsAppError::RateLimited {
+                            retry_after_ms: (wait.as_millis() as u64).max(1),
+                        });
+                    }
+                    Err(wait) => wait,
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Like dispatch, but with an explicit deadline. On timeout the command may still
+    // complete in the bot task; only the caller stops waiting for it.
+    async fn dispatch_within<T>(
+        &self,
+        timeout: std::time::Duration,
+        make_command: impl FnOnce(oneshot::Sender<Result<T, WhatsAppError>>) -> BotCommand,
+    ) -> Result<T, WhatsAppError> {
+        dispatch_to(&self.command_sink().await?, timeout, make_command).await
+    }
+
+    // The bot task's channel, cloned so the lock isn't held while a command is answered
+    async fn command_sink(&self) -> Result<mpsc::Sender<BotCommand>, WhatsAppError> {
+        let guard = self.command_tx.lock().await;
+        Ok(guard.as_ref().ok_or(WhatsAppError::NotInitialized)?.clone())
+    }
+}
+
+// Where commands go once built. The bot task's channel is the real one; keeping the
+// send/reply plumbing behind this lets it run against a stand-in without a live Bot.
+trait CommandSink {
+    fn submit(&self, command: BotCommand) -> Result<(), WhatsAppError>;
+}
+
+impl CommandSink for mpsc::Sender<BotCommand> {
+    // Fail fast instead of queueing behind a full channel: a flooding caller gets
+    // BusyTryAgain and backs off, rather than every command future stalling
+    fn submit(&self, command: BotCommand) -> Result<(), WhatsAppError> {
+        self.try_send(command).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => WhatsAppError::BusyTryAgain,
+            mpsc::error::TrySendError::Closed(_) => WhatsAppError::BotTaskGone,
+        })
+    }
+}
+
+// Sends a command carrying a reply channel and waits up to `timeout` for the answer
+async fn dispatch_to<T>(
+    sink: &impl CommandSink,
+    timeout: std::time::Duration,
+    make_command: impl FnOnce(oneshot::Sender<Result<T, WhatsAppError>>) -> BotCommand,
+) -> Result<T, WhatsAppError> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    sink.submit(make_command(reply_tx))?;
+    
+    tokio::time::timeout(timeout, reply_rx)
+        .await
+        .map_err(|_| WhatsAppError::Timeout(timeout.as_secs()))?
+        .map_err(|_| WhatsAppError::BotTaskGone)?
+}
+
+// Hands a built text message to the bot task and waits for its WhatsApp id
+async fn route_text(
+    sink: &impl CommandSink,
+    timeout: std::time::Duration,
+    jid: Jid,
+    message: wa::Message,
+    context: Option<wa::ContextInfo>,
+    retries: u32,
+) -> Result<Delivery<String>, WhatsAppError> {
+    dispatch_to(sink, timeout, |reply| BotCommand::SendMessage {
+        jid,
+        message,
+        context,
+        retries,
+        reply,
+    }).await
+}
+
+pub struct WhatsAppState {
+    accounts: Arc<Mutex<HashMap<String, Arc<AccountSession>>>>,
+    media_limits: Arc<Mutex<MediaLimits>>,
+    command_timeout: std::time::Duration,
+    media_timeout: std::time::Duration,
+    uploads: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    live_locations: Arc<Mutex<HashMap<String, LiveLocation>>>, // By account id
+    // Shared with every session; a std lock so emitting never needs an async context
+    event_filter: Arc<std::sync::RwLock<Option<HashSet<String>>>>,
+    rate_limiter: Arc<Mutex<Option<RateLimiter>>>,
+    // Set by set_dry_run. Deliberately not saved with the other settings: a dry run
+    // left on after a relaunch would silently swallow real sends.
+    dry_run: Arc<AtomicBool>,
+}
+
+impl WhatsAppState {
+    pub fn new() -> Self {
+        Self {
+            accounts: Arc::new(Mutex::new(HashMap::new())),
+            media_limits: Arc::new(Mutex::new(MediaLimits::default())),
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            media_timeout: DEFAULT_MEDIA_TIMEOUT,
+            uploads: Arc::new(Mutex::new(HashMap::new())),
+            live_locations: Arc::new(Mutex::new(HashMap::new())),
+            event_filter: Arc::new(std::sync::RwLock::new(None)),
+            rate_limiter: Arc::new(Mutex::new(None)),
+            dry_run: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    async fn find_session(&self, account_id: &str) -> Option<Arc<AccountSession>> {
+        self.accounts.lock().await.get(account_id).cloned()
+    }
+
+    // Session for an account that init_whatsapp has already been called for
+    async fn session(&self, account_id: &str) -> Result<Arc<AccountSession>, WhatsAppError> {
+        self.find_session(account_id)
+            .await
+            .ok_or(WhatsAppError::NotInitialized)
+    }
+
+    async fn session_or_insert(&self, account_id: &str) -> Result<Arc<AccountSession>, WhatsAppError> {
+        // The id ends up in event names, which only allow a limited character set
+        let is_valid = !account_id.is_empty()
+            && account_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid {
+            return Err(WhatsAppError::InvalidAccountId(account_id.to_string()));
+        }
+        
+        let mut accounts = self.accounts.lock().await;
+        let session = accounts
+            .entry(account_id.to_string())
+            .or_insert_with(|| {
+                Arc::new(AccountSession::new(
+                    account_id,
+                    self.command_timeout,
+                    self.event_filter.clone(),
+                    self.rate_limiter.clone(),
+                    self.dry_run.clone(),
+                ))
+            });
+        Ok(session.clone())
+    }
+
+    async fn current_settings(&self) -> Settings {
+        let event_filter = self
+            .event_filter
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|filter| {
+                let mut events: Vec<String> = filter.iter().cloned().collect();
+                events.sort();
+                events
+            });
+        let rate_limit = self.rate_limiter.lock().await.as_ref().map(|limiter| RateLimitSettings {
+            per_minute: limiter.per_minute,
+            mode: limiter.mode.as_str().to_string(),
+        });
+        Settings {
+            event_filter,
+            debug_logging: DEBUG_LOGGING.load(Ordering::Relaxed),
+            raw_events: RAW_EVENTS.load(Ordering::Relaxed),
+            idle_timeout_minutes: (IDLE_TIMEOUT_SECS.load(Ordering::Relaxed) / 60) as u32,
+            rate_limit,
+            media_limits: Some(*self.media_limits.lock().await),
+        }
+    }
+
+    async fn apply_settings(&self, settings: Settings) {
+        *self.event_filter.write().unwrap_or_else(|e| e.into_inner()) =
+            settings.event_filter.map(|events| events.into_iter().collect());
+        DEBUG_LOGGING.store(settings.debug_logging, Ordering::Relaxed);
+        RAW_EVENTS.store(settings.raw_events, Ordering::Relaxed);
+        IDLE_TIMEOUT_SECS.store(u64::from(settings.idle_timeout_minutes) * 60, Ordering::Relaxed);
+        // A bad mode in a hand-edited file just leaves the limit off
+        *self.rate_limiter.lock().await = settings
+            .rate_limit
+            .filter(|limit| limit.per_minute > 0)
+            .and_then(|limit| {
+                RateLimitMode::parse(&limit.mode).ok().map(|mode| RateLimiter::new(limit.per_minute, mode))
+            });
+        *self.media_limits.lock().await = settings.media_limits.unwrap_or_default();
+    }
+
+    // Called by init_whatsapp; a missing or unreadable file keeps the current settings
+    async fn load_settings(&self, window: &Window) {
+        let Some(path) = settings_file(window.app_handle()) else {
+            return;
+        };
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            return;
+        };
+        match serde_json::from_str::<Settings>(&contents) {
+            Ok(settings) => {
+                log_event(window, "info", format!("Loaded settings from {}", path.display()));
+                self.apply_settings(settings).await;
+            }
+            Err(e) => log_event(window, "warn", format!("Ignoring unreadable settings file {}: {}", path.display(), e)),
+        }
+    }
+
+    // Called after every settings command. Failing to save isn't worth failing the
+    // command over; the setting still applies until the app exits.
+    async fn save_settings(&self, window: &Window) {
+        let Some(path) = settings_file(window.app_handle()) else {
+            return;
+        };
+        let settings = self.current_settings().await;
+        let result = serde_json::to_string_pretty(&settings)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(&path, json).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            log_event(window, "error", format!("Failed to save settings to {}: {}", path.display(), e));
+        }
+    }
+
+    // Called on app exit: disconnects every running bot task so the websocket closes
+    // cleanly and each task drops its SQLite store before the process goes away
+    pub async fn shutdown_all(&self) {
+        let sessions: Vec<Arc<AccountSession>> = self.accounts.lock().await.values().cloned().collect();
+        for session in sessions {
+            if session.command_tx.lock().await.is_none() {
+                continue;
+            }
+            // The app is exiting, so there may be no window left to log to
+            log_console("info", &format!("Shutting down account '{}'", session.account_id));
+            if let Err(e) = session.dispatch_within(SHUTDOWN_TIMEOUT, |reply| BotCommand::Shutdown { reply }).await {
+                log_console("error", &format!("Failed to shut down account '{}': {}", session.account_id, e));
+            }
+        }
+        // The reply is sent just before the bot task returns and releases the store
+        tokio::time::sleep(SHUTDOWN_DRAIN).await;
+    }
+}
+
+// Serializable QR code event for frontend
+#[derive(Clone, Serialize)]
+struct QrCodeEvent {
+    code: String,
+    ttl_seconds: u64,
+}
+
+// Serializable notice that a previously shown QR code can no longer be scanned
+#[derive(Clone, Serialize)]
+struct QrExpiredEvent {
+    code: String,
+}
+
+// Serializable phone-number pairing code event for frontend
+#[derive(Clone, Serialize)]
+struct PairingCodeEvent {
+    code: String,
+}
+
+// Serializable media upload progress event for frontend
+#[derive(Clone, Serialize)]
+struct UploadProgressEvent {
+    message_id_placeholder: String,
+    bytes_sent: u64,
+    total: u64,
+}
+
+// Serializable reconnection attempt event for frontend
+#[derive(Clone, Serialize)]
+struct ReconnectingEvent {
+    attempt: u32,
+    max_attempts: u32,
+    delay_secs: u64,
+}
+
+// Serializable connection lifecycle change for frontend
+#[derive(Clone, Serialize)]
+struct ConnectionStateEvent {
+    state: &'static str,
+    reason: String,
+}
+
+// Serializable outbound message status change for frontend
+#[derive(Clone, Serialize)]
+struct MessageStatusEvent {
+    message_id: String,
+    status: MessageStatus,
+}
+
+// Serializable contact presence change for frontend (online, typing, last seen)
+#[derive(Clone, Serialize)]
+struct PresenceUpdateEvent {
+    jid: String,
+    status: &'static str, // "available", "unavailable", "typing", "recording" or "paused"
+    last_seen: Option<i64>,
+}
+
+// Serializable warning that the session is stored somewhere unexpected
+#[derive(Clone, Serialize)]
+struct StorageFallbackEvent {
+    attempted_path: String,
+    fallback_path: String,
+    reason: String,
+}
+
+// Serializable notice that the stored session can't be used; reset_session recovers
+#[derive(Clone, Serialize)]
+struct SessionCorruptEvent {
+    reason: String,
+}
+
+// Serializable outcome of a message sent from the offline queue
+#[derive(Clone, Serialize)]
+struct QueuedMessageEvent {
+    local_id: String,
+    message_id: Option<String>,
+    error: Option<String>,
+    dry_run: Option<String>, // The message preview, when dry run kept it from being sent
+}
+
+// Serializable per-recipient outcome of a bulk send, emitted as each send resolves
+#[derive(Clone, Serialize)]
+struct BulkProgressEvent {
+    index: usize,
+    total: usize,
+    contact: String,
+    status: &'static str, // "sent", "dry-run" or "failed"
+    message_id: Option<String>,
+    error: Option<String>,
+}
+
+// Serializable notice that someone voted on a poll. The chosen options are encrypted
+// with the poll's secret and aren't decoded here.
+#[derive(Clone, Serialize)]
+struct PollVoteEvent {
+    poll_message_id: String,
+    voter: String,
+    timestamp: i64,
+}
+
+// Serializable choice made on a button or list message we sent
+#[derive(Clone, Serialize)]
+struct ButtonReplyEvent {
+    from: String,
+    message_id: String,
+    selected_id: String,
+    selected_text: Option<String>,
+    original_message_id: Option<String>,
+}
+
+// Serializable notice that an incoming message couldn't be decrypted, so the UI can show
+// "Waiting for this message" (the sender's phone is asked to re-send it)
+#[derive(Clone, Serialize)]
+struct DecryptionFailedEvent {
+    from: String,
+    chat: String,
+    message_id: String,
+    timestamp: i64,
+}
+
+// Serializable notice that a live location's duration has run out
+#[derive(Clone, Serialize)]
+struct LiveLocationEndedEvent {
+    jid: String,
+}
+
+// Serializable debug dump of a protocol event, for features not surfaced by typed events yet
+#[derive(Clone, Serialize)]
+struct RawEvent {
+    debug: String,
+}
+
+// Serializable log line for the frontend, so release builds can capture diagnostics
+#[derive(Clone, Serialize)]
+struct DebugLogEvent {
+    level: String,
+    message: String,
+}
+
+// Serializable incoming text message event for frontend
+#[derive(Clone, Serialize)]
+struct IncomingMessageEvent {
+    from: String,
+    text: String,
+    timestamp: i64,
+    message_id: String,
+}
+
+// Serializable notice that the messages WhatsApp held while we were offline have all arrived
+#[derive(Clone, Serialize)]
+struct OfflineSyncCompleteEvent {
+    count: u64,
+}
+
+// Serializable incoming media message event; the bytes stay on WhatsApp's servers
+// until download_media is called with the message id
+#[derive(Clone, Serialize)]
+struct MediaMessageEvent {
+    from: String,
+    message_id: String,
+    media_type: &'static str,
+    mime: Option<String>,
+    file_length: u64,
+    caption: Option<String>,
+    has_thumbnail: bool,
+}
+
+// Serializable message a dry run built but didn't send; `message` is its Debug text,
+// since the protobuf types have no JSON form
+#[derive(Clone, Serialize)]
+struct DryRunMessageEvent {
+    jid: String,
+    message: String,
+}
+
+// Serializable display name change for a contact
+#[derive(Clone, Serialize)]
+struct ContactUpdatedEvent {
+    jid: String,
+    pushname: String,
+}
+
+// Serializable notice that create_group made a new group
+#[derive(Clone, Serialize)]
+struct GroupCreatedEvent {
+    jid: String,
+    subject: String,
+    participants: Vec<String>,
+}
+
+// Serializable notice that this app changed a group's subject or description
+#[derive(Clone, Serialize)]
+struct GroupMetadataUpdatedEvent {
+    jid: String,
+    field: &'static str, // "subject" or "description"
+    value: String,
+}
+
+// Shuts the bot task down if neither PairSuccess nor Connected arrives within INIT_TIMEOUT
+fn arm_init_timeout(window: &Window, session: &Arc<AccountSession>) {
+    let window = window.clone();
+    let session = session.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(INIT_TIMEOUT).await;
+        if *session.is_authenticated.lock().await || *session.is_ready.lock().await {
+            return;
+        }
+        let Some(tx) = session.command_tx.lock().await.clone() else {
+            return;
+        };
+        
+        log_event(&window, "error", format!("Pairing not completed within {} seconds, stopping", INIT_TIMEOUT.as_secs()));
+        *session.current_qr.lock().await = None;
+        session.emit(&window, "init-timeout", ());
+        
+        // Nobody waits on the reply; the bot task clears its channel and state as for any disconnect
+        let (reply, _) = oneshot::channel();
+        let _ = tx.send(BotCommand::Shutdown { reply }).await;
+    });
+}
+
+// Tauri Command: Initialize WhatsApp connection
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn init_whatsapp(
+    account_id: String,
+    max_reconnect_attempts: Option<u32>,
+    db_name: Option<String>, // e.g. "work.db"; defaults to one file per account
+    use_pairing_code: Option<String>, // Phone number to link with a pairing code instead of a QR
+    qr_timeout_secs: Option<u64>, // How long after the last QR to give up on pairing
+    proxy: Option<ProxyConfig>, // Connects directly when absent
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session_or_insert(&account_id).await?;
+    let pairing_phone = use_pairing_code
+        .filter(|phone| !phone.trim().is_empty())
+        .map(|phone| normalize_phone(&phone))
+        .transpose()?;
+    let proxy_client = proxy.as_ref().map(ProxyConfig::http_client).transpose()?;
+    
+    // Settings saved by a previous run apply before anything connects
+    state.load_settings(&window).await;
+    
+    // A live channel means a bot task already owns this account; the flag covers
+    // a concurrent call that hasn't got as far as creating its channel yet
+    {
+        let mut is_initializing = session.is_initializing.lock().await;
+        if *is_initializing || session.command_tx.lock().await.is_some() {
+            log_event(&window, "info", format!("Ignoring init_whatsapp for '{}': already initialized", account_id));
+            return Err(WhatsAppError::AlreadyInitialized);
+        }
+        *is_initializing = true;
+    }
+    
+    let backend = match open_store(&window, &session, db_name, &account_id).await {
+        Ok(backend) => backend,
+        Err(e) => {
+            *session.is_initializing.lock().await = false;
+            return Err(e);
+        }
+    };
+
+    if proxy_client.is_some() {
+        log_event(&window, "info", "Routing HTTP requests through the configured proxy");
+    }
+
+    let (tx, mut rx) = mpsc::channel::<BotCommand>(COMMAND_CHANNEL_CAPACITY);
+    *session.command_tx.lock().await = Some(tx);
+    *session.is_initializing.lock().await = false;
+    *session.was_replaced.lock().await = false;
+    *session.status.lock().await = ConnectionStatus::Uninitialized;
+
+    let max_reconnect_attempts = max_reconnect_attempts.unwrap_or(DEFAULT_MAX_RECONNECT_ATTEMPTS);
+    let qr_timeout = std::time::Duration::from_secs(qr_timeout_secs.unwrap_or(DEFAULT_QR_TIMEOUT_SECS));
+    let window_clone = window.clone();
+    let session_clone = session.clone();
+    // Set by the first QR or pairing code of this init, so later codes don't restart the clock
+    let init_timer_armed = Arc::new(AtomicBool::new(false));
+    
+    tokio::spawn(async move {
+        let session_for_events = session_clone.clone();
+        let window_for_logout = window_clone.clone();
+        
+        let mut builder = Bot::builder()
+            .with_backend(backend.clone())
+            .with_transport_factory(TokioWebSocketTransportFactory::new());
+        builder = match proxy_client {
+            Some(client) => builder.with_http_client(client),
+            None => builder.with_http_client(UreqHttpClient::new()),
+        };
+        builder = builder
+            .on_event(move |event, client| {
+                let window = window_clone.clone();
+                let session = session_for_events.clone();
+                let init_timer_armed = init_timer_armed.clone();
+                
+                async move {
+                    if RAW_EVENTS.load(Ordering::Relaxed) {
+                        session.emit(&window, "raw-event", RawEvent { debug: format!("{:?}", event) });
+                    }
+                    
+                    match event {
+                        Event::PairingQrCode { code, timeout } => {
+                            log_event(&window, "info", "QR Code generated");
+                            *session.status.lock().await = ConnectionStatus::WaitingForQr;
+                            if !init_timer_armed.swap(true, Ordering::SeqCst) {
+                                arm_init_timeout(&window, &session);
+                            }
+                            
+                            let previous = session.current_qr.lock().await.replace(code.clone());
+                            if let Some(previous) = previous {
+                                session.emit(&window, "qr-expired", QrExpiredEvent { code: previous });
+                            }
+                            
+                            session.emit(&window, "qr-code", QrCodeEvent {
+                                code: code.clone(),
+                                ttl_seconds: timeout.as_secs(),
+                            });
+                            
+                            // If this is still the latest code once the window passes, pairing has stalled
+                            let window = window.clone();
+                            let session = session.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(qr_timeout).await;
+                                let is_latest = session.current_qr.lock().await.as_deref() == Some(code.as_str());
+                                if is_latest && !*session.is_authenticated.lock().await {
+                                    log_event(&window, "info", "QR pairing timed out");
+                                    session.emit(&window, "qr-timeout", ());
+                                }
+                            });
+                        }
+                        
+                        Event::PairingCode { code, .. } => {
+                            log_event(&window, "info", "Pairing code generated");
+                            *session.status.lock().await = ConnectionStatus::WaitingForQr;
+                            if !init_timer_armed.swap(true, Ordering::SeqCst) {
+                                arm_init_timeout(&window, &session);
+                            }
+                            session.emit(&window, "pairing-code", PairingCodeEvent { code });
+                        }
+                        
+                        Event::PairSuccess(pair) => {
+                            log_event(&window, "info", "Pair success event received");
+                            *session.self_jid.lock().await = Some(bare_jid(&pair.id));
+                            *session.current_qr.lock().await = None;
+                            *session.is_authenticated.lock().await = true;
+                            *session.status.lock().await = ConnectionStatus::Authenticated;
+                            session.emit(&window, "auth-success", ());
+                        }
+                        
+                        Event::Connected(_) => {
+                            log_event(&window, "info", "Connected event received - Bot is fully ready");
+                            // Restored sessions never see PairSuccess, so read our number from the store
+                            if let Some(own_jid) = client.get_pn().await {
+                                *session.self_jid.lock().await = Some(bare_jid(&own_jid));
+                            }
+                            *session.is_authenticated.lock().await = true;
+                            *session.is_ready.lock().await = true;
+                            session.connected.notify_waiters();
+                            *session.status.lock().await = ConnectionStatus::Connected;
+                            session.emit(&window, "auth-success", ());
+                            
+                            // Hand queued sends to the bot task in order, each paced by the rate
+                            // limiter like any other send. Spawned because the channel can
+                            // fill up, and the bot task is what drains it.
+                            let tx = session.command_tx.lock().await.clone();
+                            if let Some(tx) = tx {
+                                let queued: Vec<BotCommand> = session.pending.lock().await.drain(..).collect();
+                                if !queued.is_empty() {
+                                    log_event(&window, "info", format!("Flushing {} queued message(s)", queued.len()));
+                                    let session = session.clone();
+                                    tokio::spawn(async move {
+                                        for command in queued {
+                                            session.wait_for_send_slot().await;
+                                            if tx.send(command).await.is_err() {
+                                                break;
+                                            }
+                                        }
+                                    });
+                                }
+                            }
+                        }
+                        
+                        Event::LoggedOut(logged_out) => {
+                            log_event(&window, "info", "Logged out event received");
+                            session.emit(&window, "connection-state", ConnectionStateEvent {
+                                state: "logged-out",
+                                reason: format!("Logged out by server: {:?}", logged_out.reason),
+                            });
+                            *session.is_authenticated.lock().await = false;
+                            *session.is_ready.lock().await = false;
+                            *session.status.lock().await = ConnectionStatus::LoggedOut;
+                            *session.self_jid.lock().await = None;
+                            session.emit(&window, "logged-out", ());
+                        }
+                        
+                        Event::Disconnected(_) => {
+                            log_event(&window, "info", "Disconnected event received");
+                            // A sync cut short never completes; don't sit on what did arrive
+                            session.end_offline_sync(&window).await;
+                            // Dropped on purpose by the idle timeout, which sends its own event
+                            if !*session.is_idle.lock().await {
+                                session.emit(&window, "connection-state", ConnectionStateEvent {
+                                    state: "disconnected",
+                                    reason: "Connection to WhatsApp was lost".to_string(),
+                                });
+                            }
+                        }
+                        
+                        Event::StreamReplaced(_) => {
+                            log_event(&window, "info", "Stream replaced event received");
+                            // Another client took over these credentials. Reconnecting would just
+                            // kick that one off in turn, so stop until init_whatsapp is called again.
+                            *session.was_replaced.lock().await = true;
+                            session.emit(&window, "connection-state", ConnectionStateEvent {
+                                state: "stream-replaced",
+                                reason: "This session was opened on another device".to_string(),
+                            });
+                            session.emit(&window, "session-replaced", ());
+                            client.disconnect().await;
+                        }
+                        
+                        Event::TemporaryBan(ban) => {
+                            log_event(&window, "info", format!("Temporary ban event received: {:?}", ban));
+                            session.emit(&window, "connection-state", ConnectionStateEvent {
+                                state: "temporary-ban",
+                                reason: format!("Account temporarily banned ({:?}), expires in {:?}", ban.code, ban.expire),
+                            });
+                        }
+                        
+                        Event::ConnectFailure(failure) => {
+                            log_event(&window, "info", format!("Connect failure event received: {:?}", failure));
+                            session.emit(&window, "connection-state", ConnectionStateEvent {
+                                state: "connect-failure",
+                                reason: format!("{:?}: {}", failure.reason, failure.message),
+                            });
+                        }
+                        
+                        Event::Receipt(receipt) => {
+                            let status = match receipt.r#type {
+                                ReceiptType::Delivered => MessageStatus::Delivered,
+                                ReceiptType::Read | ReceiptType::Played => MessageStatus::Read,
+                                _ => return,
+                            };
+                            
+                            let mut statuses = session.message_statuses.lock().await;
+                            for message_id in receipt.message_ids {
+                                // Only track our own messages, and never move a status backwards
+                                match statuses.get_mut(&message_id) {
+                                    Some(current) if *current < status => *current = status,
+                                    _ => continue,
+                                }
+                                session.emit(&window, "message-status", MessageStatusEvent { message_id, status });
+                            }
+                        }
+                        
+                        Event::Presence(update) => {
+                            session.emit(&window, "presence-update", PresenceUpdateEvent {
+                                jid: bare_jid(&update.from),
+                                status: if update.unavailable { "unavailable" } else { "available" },
+                                last_seen: update.last_seen.map(|t| t.timestamp()),
+                            });
+                        }
+                        
+                        Event::ChatPresence(update) => {
+                            let status = match (update.state, update.media) {
+                                (ChatPresence::Composing, ChatPresenceMedia::Audio) => "recording",
+                                (ChatPresence::Composing, _) => "typing",
+                                _ => "paused",
+                            };
+                            session.emit(&window, "presence-update", PresenceUpdateEvent {
+                                jid: bare_jid(&update.source.sender),
+                                status,
+                                last_seen: None,
+                            });
+                        }
+                        
+                        Event::PushNameUpdate(update) => {
+                            session.update_contact_name(&window, &update.jid, &update.new_push_name).await;
+                        }
+                        
+                        Event::UndecryptableMessage(undecryptable) => {
+                            let info = &undecryptable.info;
+                            log_event(&window, "error", format!("Could not decrypt message {} from {}", info.id, info.source.sender));
+                            session.emit(&window, "decryption-failed", DecryptionFailedEvent {
+                                from: bare_jid(&info.source.sender),
+                                chat: bare_jid(&info.source.chat),
+                                message_id: info.id.clone(),
+                                timestamp: info.timestamp.timestamp(),
+                            });
+                        }
+                        
+                        Event::Message(msg, info) => {
+                            log_event(&window, "info", format!("Message received from: {:?}", info.source.sender));
+                            
+                            // Offline messages can be replayed after a reconnect; checked before
+                            // caching so a replay doesn't overwrite or refresh the cached copies
+                            if !session.seen_message_ids.lock().await.insert(info.id.clone(), ()) {
+                                log_event(&window, "info", format!("Skipping duplicate message {}", info.id));
+                                return;
+                            }
+                            
+                            if let Some(media) = media_reference(&msg) {
+                                session.media_cache.lock().await.insert(info.id.clone(), media);
+                            }
+                            session.forwardable.lock().await.insert(info.id.clone(), wa::Message::clone(&msg));
+                            
+                            // Our own messages echoed from other linked devices aren't "incoming",
+                            // but they still belong in the chat's history
+                            let from_self = info.source.is_from_me || session.is_self(&info.source.sender).await;
+                            // Notes written to ourselves on another device are the whole point of the
+                            // self chat, so those still reach the frontend
+                            let self_chat = session.is_self(&info.source.chat).await;
+                            if !from_self {
+                                session.update_contact_name(&window, &info.source.sender, &info.push_name).await;
+                            }
+                            
+                            let chat_jid = bare_jid(&info.source.chat);
+                            let preview = message_preview(&msg);
+                            session.record_chat_activity(&chat_jid, preview.clone(), info.timestamp.timestamp(), !from_self).await;
+                            session.history.lock().await.push(&window, StoredMessage {
+                                id: info.id.clone(),
+                                chat_jid,
+                                sender: bare_jid(&info.source.sender),
+                                from_me: from_self,
+                                text: preview,
+                                timestamp: info.timestamp.timestamp(),
+                            });
+                            
+                            // Either side of the chat (or another of our devices) can change the timer
+                            if let Some(protocol) = &msg.protocol_message {
+                                if protocol.r#type == Some(wa::message::protocol_message::Type::EphemeralSetting as i32) {
+                                    let seconds = protocol.ephemeral_expiration.unwrap_or(0);
+                                    let mut timers = session.disappearing_timers.lock().await;
+                                    if seconds == 0 {
+                                        timers.remove(&bare_jid(&info.source.chat));
+                                    } else {
+                                        timers.insert(bare_jid(&info.source.chat), seconds);
+                                    }
+                                }
+                            }
+                            
+                            if from_self && !self_chat {
+                                return;
+                            }
+                            
+                            if let Some((selected_id, selected_text, original_message_id)) = button_reply(&msg) {
+                                session.emit(&window, "button-reply", ButtonReplyEvent {
+                                    from: info.source.sender.to_string(),
+                                    message_id: info.id.clone(),
+                                    selected_id,
+                                    selected_text,
+                                    original_message_id,
+                                });
+                                return;
+                            }
+                            
+                            if let Some(vote) = &msg.poll_update_message {
+                                let poll_message_id = vote
+                                    .poll_creation_message_key
+                                    .as_ref()
+                                    .and_then(|key| key.id.clone())
+                                    .unwrap_or_default();
+                                session.emit(&window, "poll-vote", PollVoteEvent {
+                                    poll_message_id,
+                                    voter: bare_jid(&info.source.sender),
+                                    timestamp: info.timestamp.timestamp(),
+                                });
+                                return;
+                            }
+                            
+                            // media_reference above already cached what download_media needs
+                            if let Some(media) = media_message_event(&msg, info.source.sender.to_string(), info.id.clone()) {
+                                session.emit(&window, "media-message-received", media);
+                            }
+                            
+                            if let Some(text) = extract_message_text(&msg) {
+                                let event = IncomingMessageEvent {
+                                    from: info.source.sender.to_string(),
+                                    text,
+                                    timestamp: info.timestamp.timestamp(),
+                                    message_id: info.id.clone(),
+                                };
+                                // A reconnect can replay hundreds of messages; batching them
+                                // lets the UI redraw once instead of once per message
+                                let mut batch = session.offline_batch.lock().await;
+                                match batch.as_mut() {
+                                    Some(pending) => {
+                                        pending.push(event);
+                                        if pending.len() >= MAX_OFFLINE_BATCH {
+                                            session.emit(&window, "messages-batch", std::mem::take(pending));
+                                        }
+                                    }
+                                    None => session.emit(&window, "message-received", event),
+                                }
+                            }
+                        }
+                        
+                        Event::OfflineSyncPreview(preview) => {
+                            log_event(&window, "info", format!("Offline sync starting: {} message(s) waiting", preview.messages));
+                            *session.offline_batch.lock().await = Some(Vec::new());
+                        }
+                        
+                        Event::OfflineSyncCompleted(completed) => {
+                            log_event(&window, "info", "Offline sync complete");
+                            session.end_offline_sync(&window).await;
+                            session.emit(&window, "offline-sync-complete", OfflineSyncCompleteEvent {
+                                count: u64::try_from(completed.count).unwrap_or(0),
+                            });
+                        }
+                        
+                        _ => {}
+                    }
+                }
+            });
+        
+        // Link with phone number: WhatsApp sends a notification to the phone instead of showing a QR
+        if let Some(phone_number) = pairing_phone {
+            builder = builder.with_pair_code(PairCodeOptions {
+                phone_number,
+                ..Default::default()
+            });
+        }
+        
+        let bot_result = builder.build().await;
+
+        match bot_result {
+            Ok(mut bot) => {
+                log_event(&window_for_logout, "info", "Bot built successfully, starting...");
+                let client = bot.client();
+                // Held until state is reset so the caller sees a fully torn down session
+                let mut logout_reply = None;
+                let mut shutdown_reply = None;
+                let mut attempt: u32 = 0;
+                let mut media_lane = MediaLane::default();
+                // Commands that arrived while idle, handled first once reconnected
+                let mut woke_by: VecDeque<BotCommand> = VecDeque::new();
+                // Restarted by every command except pings, which the UI may send on a timer
+                let mut last_activity = tokio::time::Instant::now();
+                
+                loop {
+                    let handle = match bot.run().await {
+                        Ok(handle) => handle,
+                        Err(e) => {
+                            log_event(&window_for_logout, "error", format!("Failed to run bot: {}", e));
+                            break;
+                        }
+                    };
+                    log_event(&window_for_logout, "info", "Bot started successfully");
+                    
+                    // Process commands via channel on the SAME task as the bot.
+                    // This avoids cross-thread Rc access that causes crashes.
+                    tokio::pin!(handle);
+                    let mut went_idle = false;
+                    let connection_dropped = loop {
+                        media_lane.start_next(|command| {
+                            Box::pin(process_command(&window_for_logout, &client, &backend, &session_clone, command))
+                        });
+                        
+                        tokio::select! {
+                            _ = media_lane.finished() => {
+                                last_activity = tokio::time::Instant::now();
+                            }
+                            cmd = async {
+                                if woke_by.is_empty() {
+                                    return rx.recv().await;
+                                }
+                                // Held until Event::Connected, or it would fail on a connection
+                                // still coming up; anything sent meanwhile waits behind it
+                                session_clone.wait_until_ready().await;
+                                woke_by.pop_front()
+                            } => {
+                                if !matches!(cmd, Some(BotCommand::Ping { .. })) {
+                                    last_activity = tokio::time::Instant::now();
+                                }
+                                match cmd {
+                                    Some(BotCommand::Logout { reply }) => {
+                                        log_event(&window_for_logout, "info", "Processing Logout command");
+                                        let result = client.logout().await
+                                            .map_err(|e| WhatsAppError::LogoutFailed(e.to_string()));
+                                        logout_reply = Some((reply, result));
+                                        break false;
+                                    }
+                                    Some(BotCommand::Shutdown { reply }) => {
+                                        log_event(&window_for_logout, "info", "Processing Shutdown command");
+                                        client.disconnect().await;
+                                        shutdown_reply = Some(reply);
+                                        break false;
+                                    }
+                                    Some(command) => {
+                                        if let Some(command) = media_lane.offer(command) {
+                                            process_command(&window_for_logout, &client, &backend, &session_clone, command).await;
+                                        }
+                                    }
+                                    None => {
+                                        log_event(&window_for_logout, "info", "Command channel closed");
+                                        break false;
+                                    }
+                                }
+                            }
+                            _ = &mut handle => {
+                                log_event(&window_for_logout, "info", "Bot handle completed");
+                                break true;
+                            }
+                            _ = idle_timer(last_activity), if media_lane.is_empty() => {
+                                log_event(&window_for_logout, "info", "No commands within the idle timeout, disconnecting");
+                                // Set first so Event::Disconnected knows this wasn't a lost connection
+                                *session_clone.is_ready.lock().await = false;
+                                *session_clone.is_idle.lock().await = true;
+                                client.disconnect().await;
+                                went_idle = true;
+                                break false;
+                            }
+                        }
+                    };
+                    
+                    if went_idle {
+                        *session_clone.status.lock().await = ConnectionStatus::Authenticated;
+                        session_clone.emit(&window_for_logout, "idle-disconnected", ());
+                        
+                        // Stay offline until something needs the connection again
+                        let mut stop = false;
+                        loop {
+                            match rx.recv().await {
+                                Some(BotCommand::Ping { reply }) => {
+                                    let _ = reply.send(Ok(()));
+                                }
+                                Some(BotCommand::Shutdown { reply }) => {
+                                    shutdown_reply = Some(reply);
+                                    stop = true;
+                                    break;
+                                }
+                                Some(BotCommand::Wake) => break,
+                                Some(command) => {
+                                    // Handled once reconnected, ahead of anything sent meanwhile.
+                                    // Held here rather than put back on the channel, which
+                                    // may be full by then.
+                                    woke_by.push_back(command);
+                                    break;
+                                }
+                                None => {
+                                    stop = true;
+                                    break;
+                                }
+                            }
+                        }
+                        *session_clone.is_idle.lock().await = false;
+                        if stop {
+                            break;
+                        }
+                        log_event(&window_for_logout, "info", "Reconnecting after idle disconnect");
+                        last_activity = tokio::time::Instant::now();
+                        continue;
+                    }
+                    
+                    // An explicit LoggedOut event clears is_authenticated; don't fight it
+                    if !connection_dropped
+                        || *session_clone.was_replaced.lock().await
+                        || !*session_clone.is_authenticated.lock().await
+                    {
+                        break;
+                    }
+                    
+                    // A session that reached Connected earns a fresh set of retries
+                    if std::mem::replace(&mut *session_clone.is_ready.lock().await, false) {
+                        attempt = 0;
+                    }
+                    
+                    attempt += 1;
+                    if attempt > max_reconnect_attempts {
+                        log_event(&window_for_logout, "error", format!("Giving up after {} reconnection attempts", max_reconnect_attempts));
+                        break;
+                    }
+                    
+                    let delay = reconnect_delay(attempt);
+                    log_event(&window_for_logout, "info", format!("Connection lost, reconnecting in {:?} (attempt {})", delay, attempt));
+                    session_clone.emit(&window_for_logout, "reconnecting", ReconnectingEvent {
+                        attempt,
+                        max_attempts: max_reconnect_attempts,
+                        delay_secs: delay.as_secs(),
+                    });
+                    tokio::time::sleep(delay).await;
+                }
+                
+                // Drop the sender so a later init_whatsapp opens a fresh channel
+                *session_clone.command_tx.lock().await = None;
+                *session_clone.is_ready.lock().await = false;
+                // Media sends still waiting on the lane fail with BotTaskGone
+                drop(media_lane);
+                
+                if let Some(reply) = shutdown_reply {
+                    // Credentials stay in whatsapp.db, so is_authenticated keeps meaning
+                    // "a paired session exists" and init_whatsapp reconnects without a QR
+                    log_event(&window_for_logout, "info", "Bot task ending after disconnect, session kept");
+                    *session_clone.status.lock().await = ConnectionStatus::Uninitialized;
+                    session_clone.emit(&window_for_logout, "connection-state", ConnectionStateEvent {
+                        state: "disconnected",
+                        reason: "Disconnect requested".to_string(),
+                    });
+                    let _ = reply.send(Ok(()));
+                    return;
+                }
+                
+                if *session_clone.was_replaced.lock().await {
+                    // Not a logout: the credentials are still valid, just in use elsewhere
+                    log_event(&window_for_logout, "info", "Bot task ending, session replaced by another device");
+                    *session_clone.status.lock().await = ConnectionStatus::Uninitialized;
+                    return;
+                }
+                
+                // Bot stopped - reset state
+                log_event(&window_for_logout, "info", "Bot task ending, resetting state");
+                *session_clone.is_authenticated.lock().await = false;
+                *session_clone.status.lock().await = ConnectionStatus::LoggedOut;
+                *session_clone.self_jid.lock().await = None;
+                if logout_reply.is_some() {
+                    session_clone.emit(&window_for_logout, "connection-state", ConnectionStateEvent {
+                        state: "logged-out",
+                        reason: "Logout requested".to_string(),
+                    });
+                }
+                session_clone.emit(&window_for_logout, "logged-out", ());
+                
+                if let Some((reply, result)) = logout_reply {
+                    let _ = reply.send(result);
+                }
+            }
+            Err(e) => {
+                // Building loads the device identity from the store, so failing here
+                // almost always means the saved session is unreadable
+                log_event(&window_for_logout, "error", format!("Failed to build bot: {}", e));
+                *session_clone.command_tx.lock().await = None;
+                session_clone.emit(&window_for_logout, "session-corrupt", SessionCorruptEvent {
+                    reason: e.to_string(),
+                });
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// Validates a caller-supplied database name so it can't escape the storage dir.
+// Without one, the default account keeps whatsapp.db and others get their own file.
+fn database_file_name(db_name: Option<String>, account_id: &str) -> Result<String, WhatsAppError> {
+    let Some(name) = db_name else {
+        if account_id == DEFAULT_ACCOUNT_ID {
+            return Ok(DEFAULT_DB_NAME.to_string());
+        }
+        return Ok(format!("whatsapp-{}.db", account_id));
+    };
+    
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\', ':']) || name.contains("..") {
+        return Err(WhatsAppError::InvalidDbName(name.to_string()));
+    }
+    Ok(name.to_string())
+}
+
+// Opens the account's session database in the app data dir (outside of src-tauri to avoid rebuild loops)
+async fn open_store(
+    window: &Window,
+    session: &AccountSession,
+    db_name: Option<String>,
+    account_id: &str,
+) -> Result<Arc<SqliteStore>, WhatsAppError> {
+    let app_data_dir = resolve_storage_dir(window, session)?;
+    
+    let db_path = app_data_dir.join(database_file_name(db_name, account_id)?);
+    let db_path_str = db_path.to_string_lossy().to_string();
+    
+    log_event(window, "info", format!("Using database path: {}", db_path_str));
+    let history = MessageHistory::load(window, history_file(&db_path));
+    {
+        // Seed the chat list so get_chats isn't empty straight after a restart
+        let mut chats = session.chats.lock().await;
+        for (chat_jid, messages) in &history.chats {
+            if let Some(last) = messages.last() {
+                chats.entry(chat_jid.clone()).or_insert(ChatActivity {
+                    last_message_preview: last.text.clone(),
+                    last_timestamp: last.timestamp,
+                    unread_count: 0,
+                });
+            }
+        }
+    }
+    *session.history.lock().await = history;
+    *session.db_path.lock().await = Some(db_path);
+
+    let backend = SqliteStore::new(&db_path_str).await.map_err(|e| {
+        session.emit(window, "session-corrupt", SessionCorruptEvent {
+            reason: e.to_string(),
+        });
+        WhatsAppError::InitFailed(e.to_string())
+    })?;
+    let backend = Arc::new(backend);
+    *session.store.lock().await = Arc::downgrade(&backend);
+    Ok(backend)
+}
+
+// Message history lives beside the session database: whatsapp.db -> whatsapp.messages.jsonl
+fn history_file(db_path: &std::path::Path) -> std::path::PathBuf {
+    db_path.with_extension("messages.jsonl")
+}
+
+// Deletes a SQLite database along with its WAL and shared-memory sidecar files
+// Windows refuses to delete a file while any handle is open on it, so a lock that is
+// only just being released is retried a few times before giving up
+async fn remove_db_files(db_path: &std::path::Path) -> Result<(), WhatsAppError> {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        let mut attempt = 1;
+        loop {
+            match std::fs::remove_file(&path) {
+                Ok(()) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) if is_sharing_violation(&e) && attempt < DB_DELETE_ATTEMPTS => {
+                    attempt += 1;
+                    tokio::time::sleep(DB_DELETE_RETRY_DELAY).await;
+                }
+                Err(e) if is_sharing_violation(&e) => {
+                    return Err(WhatsAppError::DatabaseLocked(path.to_string_lossy().into_owned()));
+                }
+                Err(e) => return Err(WhatsAppError::FileWrite(e.to_string())),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Helper function to spot a delete blocked by another open handle: ERROR_SHARING_VIOLATION
+// or ERROR_LOCK_VIOLATION, though some Windows versions report plain access denied
+fn is_sharing_violation(e: &std::io::Error) -> bool {
+    cfg!(windows)
+        && (matches!(e.raw_os_error(), Some(32 | 33)) || e.kind() == std::io::ErrorKind::PermissionDenied)
+}
+
+// Helper function to locate the settings file; None if the platform has no app data dir
+fn settings_file(app: &AppHandle) -> Option<std::path::PathBuf> {
+    app.path().app_data_dir().ok().map(|dir| dir.join(SETTINGS_FILE_NAME))
+}
+
+// Picks the directory for whatsapp.db: the app data dir, or a temp dir when that
+// can't be created (sandboxed or permission-denied environments)
+fn resolve_storage_dir(window: &Window, session: &AccountSession) -> Result<std::path::PathBuf, WhatsAppError> {
+    let app_handle = window.app_handle();
+    
+    let (attempted_path, reason) = match app_handle.path().app_data_dir() {
+        Ok(app_data_dir) => match std::fs::create_dir_all(&app_data_dir) {
+            Ok(()) => return Ok(app_data_dir),
+            Err(e) => (app_data_dir.to_string_lossy().to_string(), e.to_string()),
+        },
+        Err(e) => ("<app data dir>".to_string(), e.to_string()),
+    };
+    log_event(window, "error", format!("Failed to use app data dir {}: {}", attempted_path, reason));
+    
+    let fallback_dir = std::env::temp_dir().join(&app_handle.config().identifier);
+    log_event(window, "info", format!("Falling back to temp storage: {}", fallback_dir.display()));
+    std::fs::create_dir_all(&fallback_dir).map_err(|e| {
+        WhatsAppError::StorageUnavailable(format!(
+            "tried {} ({}) and {} ({})",
+            attempted_path,
+            reason,
+            fallback_dir.display(),
+            e
+        ))
+    })?;
+    
+    session.emit(window, "storage-fallback", StorageFallbackEvent {
+        attempted_path,
+        fallback_path: fallback_dir.to_string_lossy().to_string(),
+        reason,
+    });
+    Ok(fallback_dir)
+}
+
+// Handles every command that doesn't affect the bot task's lifecycle.
+// Runs on the bot task so the client is never touched from another thread.
+async fn process_command(
+    window: &Window,
+    client: &Arc<Client>,
+    backend: &Arc<SqliteStore>,
+    session: &AccountSession,
+    command: BotCommand,
+) {
+    match command {
+        BotCommand::SendMessage { jid, mut message, context, retries, reply } => {
+            log_event(window, "info", "Processing SendMessage command");
+            if let (Some(context), Some(text_msg)) = (context, message.extended_text_message.as_mut()) {
+                text_msg.context_info = Some(Box::new(context));
+            }
+            session.apply_disappearing_timer(&jid, &mut message).await;
+            
+            if session.is_dry_run() {
+                let _ = reply.send(Ok(session.dry_run_send(window, &jid, &message)));
+                return;
+            }
+            // Every attempt reuses one id, so if a "failed" attempt actually reached
+            // WhatsApp the retry is deduplicated instead of delivered twice
+            let message_id = client.generate_message_id().await;
+            let mut attempt = 0;
+            let result = loop {
+                let sent = client.send_message_with_id(jid.clone(), message.clone(), message_id.clone()).await;
+                match sent.map_err(anyhow::Error::from) {
+                    Ok(msg_id) => break Ok(msg_id),
+                    Err(e) if attempt < retries && is_transient_send_error(window, &e) => {
+                        attempt += 1;
+                        let delay = reconnect_delay(attempt);
+                        log_event(window, "info", format!("Send failed ({}), retry {} of {} in {:?}", e, attempt, retries, delay));
+                        tokio::time::sleep(delay).await;
+                    }
+                    Err(e) => break Err(send_error(window, e)),
+                }
+            };
+            if let Ok(msg_id) = &result {
+                session.forwardable.lock().await.insert(msg_id.clone(), message);
+            }
+            let _ = reply.send(result.map(Delivery::Sent));
+        }
+        BotCommand::SendMediaMessage {
+            jid, media_data, media_type_enum,
+            media_category, mime_type, caption,
+            file_name, ptt, view_once, gif_playback, context, jpeg_thumbnail,
+            upload_id, cancel, window, reply
+        } => {
+            log_event(&window, "info", "Processing SendMediaMessage command");
+            let result = async {
+                let total = media_data.len() as u64;
+                let dry_run = session.is_dry_run();
+                let upload = if dry_run {
+                    // Nothing is uploaded, so there's no progress to report, but a cancelled
+                    // dry run still fails like a cancelled upload would
+                    if cancel.is_cancelled() {
+                        log_event(&window, "info", format!("Upload {} cancelled", upload_id));
+                        return Err(WhatsAppError::Cancelled(upload_id.clone()));
+                    }
+                    // The preview shows what's known locally; where the file would live and
+                    // its keys only exist once WhatsApp has it. This never leaves the
+                    // preview, so nothing can try to reuse it.
+                    UploadedMedia {
+                        url: String::new(),
+                        direct_path: String::new(),
+                        media_key: Vec::new(),
+                        file_sha256: Sha256::digest(&media_data).to_vec(),
+                        file_enc_sha256: Vec::new(),
+                        file_length: total,
+                    }
+                } else {
+                    // The upload API has no progress callback, so only the
+                    // start and end of the transfer are reported
+                    session.emit(&window, "upload-progress", UploadProgressEvent {
+                        message_id_placeholder: upload_id.clone(),
+                        bytes_sent: 0,
+                        total,
+                    });
+                    log_event(&window, "info", "Uploading media...");
+                    // Biased so an upload cancelled while still queued never starts
+                    let uploaded = tokio::select! {
+                        biased;
+                        _ = cancel.cancelled() => {
+                            log_event(&window, "info", format!("Upload {} cancelled", upload_id));
+                            return Err(WhatsAppError::Cancelled(upload_id.clone()));
+                        }
+                        result = client.upload(media_data, media_type_enum) => result.map_err(|e| {
+                            log_event(&window, "error", format!("Upload failed: {}", e));
+                            upload_error(&window, e)
+                        })?,
+                    };
+                    log_event(&window, "info", "Media uploaded successfully");
+                    session.emit(&window, "upload-progress", UploadProgressEvent {
+                        message_id_placeholder: upload_id.clone(),
+                        bytes_sent: total,
+                        total,
+                    });
+                    UploadedMedia {
+                        url: uploaded.url,
+                        direct_path: uploaded.direct_path,
+                        media_key: uploaded.media_key.to_vec(),
+                        file_sha256: uploaded.file_sha256.to_vec(),
+                        file_enc_sha256: uploaded.file_enc_sha256.to_vec(),
+                        file_length: uploaded.file_length,
+                    }
+                };
+
+                let mut wa_message = media_message(
+                    &media_category, upload.clone(), mime_type, caption, file_name,
+                    ptt, view_once, gif_playback, context, jpeg_thumbnail,
+                );
+                session.apply_disappearing_timer(&jid, &mut wa_message).await;
+                
+                // Recipients' clients only hide the media after opening when it's wrapped
+                let wa_message = if view_once {
+                    wa::Message {
+                        view_once_message: Some(Box::new(wa::message::FutureProofMessage {
+                            message: Some(Box::new(wa_message)),
+                        })),
+                        ..Default::default()
+                    }
+                } else {
+                    wa_message
+                };
+
+                if dry_run {
+                    return Ok(session.dry_run_send(&window, &jid, &wa_message));
+                }
+                
+                // View once media must not be forwardable
+                let forwardable = (!view_once).then(|| wa_message.clone());
+                let msg_id = client.send_message(jid, wa_message).await
+                    .map_err(|e| send_error(&window, e))?;
+                if let Some(message) = forwardable {
+                    session.forwardable.lock().await.insert(msg_id.clone(), message);
+                }
+                Ok(Delivery::Sent((msg_id, upload)))
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::PostStatus { content, reply } => {
+            log_event(window, "info", "Processing PostStatus command");
+            let result = async {
+                let message = match content {
+                    StatusContent::Text { text, background_argb } => wa::Message {
+                        extended_text_message: Some(Box::new(wa::message::ExtendedTextMessage {
+                            text: Some(text),
+                            background_argb: Some(background_argb),
+                            text_argb: Some(0xFFFF_FFFF),
+                            ..Default::default()
+                        })),
+                        ..Default::default()
+                    },
+                    StatusContent::Media { data, media_type_enum, media_category, mime_type, caption, jpeg_thumbnail } => {
+                        let uploaded = client.upload(data, media_type_enum).await.map_err(|e| {
+                            log_event(window, "error", format!("Status upload failed: {}", e));
+                            upload_error(window, e)
+                        })?;
+                        let upload = UploadedMedia {
+                            url: uploaded.url,
+                            direct_path: uploaded.direct_path,
+                            media_key: uploaded.media_key.to_vec(),
+                            file_sha256: uploaded.file_sha256.to_vec(),
+                            file_enc_sha256: uploaded.file_enc_sha256.to_vec(),
+                            file_length: uploaded.file_length,
+                        };
+                        media_message(
+                            &media_category, upload, mime_type, caption, String::new(),
+                            false, false, false, None, jpeg_thumbnail,
+                        )
+                    }
+                };
+                client.send_message(Jid::new("status", "broadcast"), message).await
+                    .map_err(|e| send_error(window, e))
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::SendSticker { jid, media_data, is_animated, reply } => {
+            log_event(window, "info", "Processing SendSticker command");
+            let result = async {
+                let uploaded = client.upload(media_data, MediaType::Sticker)
+                    .await.map_err(|e| {
+                        log_event(window, "error", format!("Sticker upload failed: {}", e));
+                        upload_error(window, e)
+                    })?;
+                
+                let sticker_msg = wa::message::StickerMessage {
+                    url: Some(uploaded.url),
+                    direct_path: Some(uploaded.direct_path),
+                    media_key: Some(uploaded.media_key.to_vec()),
+                    file_enc_sha256: Some(uploaded.file_enc_sha256.to_vec()),
+                    file_sha256: Some(uploaded.file_sha256.to_vec()),
+                    file_length: Some(uploaded.file_length),
+                    mimetype: Some("image/webp".to_string()),
+                    is_animated: Some(is_animated),
+                    ..Default::default()
+                };
+                let mut wa_message = wa::Message {
+                    sticker_message: Some(Box::new(sticker_msg)),
+                    ..Default::default()
+                };
+                session.apply_disappearing_timer(&jid, &mut wa_message).await;
+                
+                client.send_message(jid, wa_message).await
+                    .map_err(|e| send_error(window, e))
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::SendContact { jid, mut message, reply } => {
+            log_event(window, "info", "Processing SendContact command");
+            session.apply_disappearing_timer(&jid, &mut message).await;
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::SendLocation { jid, mut message, reply } => {
+            log_event(window, "info", "Processing SendLocation command");
+            session.apply_disappearing_timer(&jid, &mut message).await;
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::SendButtons { jid, mut message, reply } => {
+            log_event(window, "info", "Processing SendButtons command");
+            session.apply_disappearing_timer(&jid, &mut message).await;
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::ForwardMessage { jid, mut message, reply } => {
+            log_event(window, "info", "Processing ForwardMessage command");
+            session.apply_disappearing_timer(&jid, &mut message).await;
+            let result = client.send_message(jid, message.clone()).await
+                .map_err(|e| send_error(window, e));
+            if let Ok(msg_id) = &result {
+                session.forwardable.lock().await.insert(msg_id.clone(), message);
+            }
+            let _ = reply.send(result);
+        }
+        BotCommand::SendPoll { jid, mut message, reply } => {
+            log_event(window, "info", "Processing SendPoll command");
+            session.apply_disappearing_timer(&jid, &mut message).await;
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::SendReaction { jid, message, reply } => {
+            log_event(window, "info", "Processing SendReaction command");
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::SetDisappearingMessages { jid, message, reply } => {
+            log_event(window, "info", "Processing SetDisappearingMessages command");
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::RevokeMessage { jid, message, reply } => {
+            log_event(window, "info", "Processing RevokeMessage command");
+            let result = client.send_message(jid, message).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::EditMessage { jid, message, reply } => {
+            log_event(window, "info", "Processing EditMessage command");
+            let result = client.send_message(jid, message).await
+                .map_err(|e| WhatsAppError::EditRejected(e.to_string()));
+            let _ = reply.send(result);
+        }
+        BotCommand::SetPresence { jid, typing, reply } => {
+            log_event(window, "info", "Processing SetPresence command");
+            let chatstate = client.chatstate();
+            let result = if typing {
+                chatstate.send_composing(&jid).await
+            } else {
+                chatstate.send_paused(&jid).await
+            };
+            let _ = reply.send(result.map_err(|e| send_error(window, e)));
+        }
+        BotCommand::SetGlobalPresence { online, reply } => {
+            log_event(window, "info", "Processing SetGlobalPresence command");
+            let presence = client.presence();
+            let result = if online {
+                presence.set_available().await
+            } else {
+                presence.set_unavailable().await
+            };
+            let _ = reply.send(result.map_err(|e| send_error(window, e)));
+        }
+        BotCommand::SubscribePresence { jid, subscribe, reply } => {
+            log_event(window, "info", "Processing SubscribePresence command");
+            let presence = client.presence();
+            let result = if subscribe {
+                presence.subscribe(&jid).await
+            } else {
+                presence.unsubscribe(&jid).await
+            };
+            let _ = reply.send(result.map_err(|e| send_error(window, e)));
+        }
+        BotCommand::SetProfileName { name, reply } => {
+            log_event(window, "info", "Processing SetProfileName command");
+            let result = client.profile().set_push_name(&name).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::SetStatusText { status, reply } => {
+            log_event(window, "info", "Processing SetStatusText command");
+            let result = client.profile().set_status_text(&status).await
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::MarkRead { jid, message_ids, reply } => {
+            log_event(window, "info", "Processing MarkRead command");
+            let count = message_ids.len();
+            let result = client.mark_as_read(&jid, None, message_ids).await
+                .map(|_| count)
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::DownloadMedia { media, reply } => {
+            log_event(window, "info", "Processing DownloadMedia command");
+            let result = client.download_from_params(
+                &media.direct_path,
+                &media.media_key,
+                &media.file_sha256,
+                &media.file_enc_sha256,
+                media.file_length,
+                media.media_type,
+            ).await
+                .map_err(|e| WhatsAppError::DownloadFailed(e.to_string()));
+            let _ = reply.send(result);
+        }
+        BotCommand::GetChats { reply } => {
+            log_event(window, "info", "Processing GetChats command");
+            let result = async {
+                // Chat activity is tracked in memory; the store only knows names
+                let contacts = backend.get_all_contacts().await
+                    .map_err(|e| WhatsAppError::StoreFailed(e.to_string()))?;
+                let mut names: HashMap<String, String> = contacts
+                    .into_iter()
+                    .filter_map(|contact| {
+                        let name = contact.full_name.or(contact.push_name)?;
+                        Some((bare_jid(&contact.jid), name))
+                    })
+                    .collect();
+                for (jid, pushname) in session.contact_names.lock().await.iter() {
+                    names.entry(jid.clone()).or_insert_with(|| pushname.clone());
+                }
+                
+                let chats = session.chats.lock().await;
+                let mut summaries: Vec<ChatSummary> = chats
+                    .iter()
+                    .map(|(jid, chat)| ChatSummary {
+                        jid: jid.clone(),
+                        name: names.get(jid).cloned(),
+                        last_message_preview: chat.last_message_preview.clone(),
+                        last_timestamp: chat.last_timestamp,
+                        unread_count: chat.unread_count,
+                    })
+                    .collect();
+                summaries.sort_by(|a, b| b.last_timestamp.cmp(&a.last_timestamp));
+                Ok(summaries)
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::Resync { reply } => {
+            log_event(window, "info", "Processing Resync command");
+            let result = client.resync_app_state().await
+                .map_err(|e| WhatsAppError::QueryFailed(e.to_string()));
+            let _ = reply.send(result);
+        }
+        BotCommand::GetContacts { reply } => {
+            log_event(window, "info", "Processing GetContacts command");
+            let pushnames = session.contact_names.lock().await.clone();
+            let result = backend.get_all_contacts().await
+                .map(|contacts| {
+                    contacts
+                        .into_iter()
+                        .map(|contact| ContactInfo {
+                            jid: contact.jid.to_string(),
+                            name: contact.full_name
+                                .or(contact.push_name)
+                                .or_else(|| pushnames.get(&bare_jid(&contact.jid)).cloned()),
+                        })
+                        .collect()
+                })
+                .map_err(|e| WhatsAppError::StoreFailed(e.to_string()));
+            let _ = reply.send(result);
+        }
+        BotCommand::CheckNumber { phone, reply } => {
+            log_event(window, "info", "Processing CheckNumber command");
+            let result = client.contacts().is_on_whatsapp(&[phone.as_str()]).await
+                .map(|results| {
+                    results
+                        .into_iter()
+                        .find(|r| r.is_registered)
+                        .map(|r| r.jid)
+                })
+                .map_err(|e| WhatsAppError::QueryFailed(e.to_string()));
+            let _ = reply.send(result);
+        }
+        BotCommand::GetGroupInfo { jid, reply } => {
+            log_event(window, "info", "Processing GetGroupInfo command");
+            let result = client.groups().get_metadata(&jid).await
+                .map(|metadata| GroupInfo {
+                    subject: metadata.subject,
+                    participants: metadata.participants.iter().map(|p| p.jid.to_string()).collect(),
+                    owner: metadata.owner.map(|owner| owner.to_string()),
+                    description: metadata.description,
+                })
+                .map_err(|e| group_query_error(window, &jid, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::CreateGroup { subject, participants, reply } => {
+            log_event(window, "info", "Processing CreateGroup command");
+            let result = client.groups().create_group(&subject, participants).await
+                .map(|metadata| metadata.id)
+                .map_err(|e| send_error(window, e));
+            let _ = reply.send(result);
+        }
+        BotCommand::UpdateGroupParticipants { jid, action, participants, reply } => {
+            log_event(window, "info", "Processing UpdateGroupParticipants command");
+            let result = async {
+                ensure_group_admin(window, client, session, &jid).await?;
+                let groups = client.groups();
+                let outcome = match action {
+                    ParticipantAction::Add => groups.add_participants(&jid, &participants).await,
+                    ParticipantAction::Remove => groups.remove_participants(&jid, &participants).await,
+                    ParticipantAction::Promote => groups.promote_participants(&jid, &participants).await,
+                    ParticipantAction::Demote => groups.demote_participants(&jid, &participants).await,
+                };
+                outcome
+                    .map(|results| results.into_iter().map(|r| {
+                        let success = r.status == "200";
+                        ParticipantResult {
+                            participant: r.jid.user,
+                            success,
+                            error: (!success).then(|| participant_error(&r.status)),
+                        }
+                    }).collect())
+                    .map_err(|e| send_error(window, e))
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::SetGroupSubject { jid, subject, reply } => {
+            log_event(window, "info", "Processing SetGroupSubject command");
+            let result = async {
+                ensure_group_admin(window, client, session, &jid).await?;
+                client.groups().set_subject(&jid, &subject).await
+                    .map_err(|e| send_error(window, e))
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::SetGroupDescription { jid, description, reply } => {
+            log_event(window, "info", "Processing SetGroupDescription command");
+            let result = async {
+                ensure_group_admin(window, client, session, &jid).await?;
+                // An empty description removes it
+                let description = (!description.is_empty()).then_some(description.as_str());
+                client.groups().set_description(&jid, description).await
+                    .map_err(|e| send_error(window, e))
+            }.await;
+            let _ = reply.send(result);
+        }
+        BotCommand::GetBusinessProfile { jid, reply } => {
+            log_event(window, "info", "Processing GetBusinessProfile command");
+            // Regular accounts have no business profile; that comes back as None, not an error
+            let result = client.contacts().get_business_profile(&jid).await
+                .map(|profile| profile.map(|p| BusinessProfile {
+                    description: p.description.filter(|d| !d.is_empty()),
+                    email: p.email.filter(|e| !e.is_empty()),
+                    website: p.websites.into_iter().next(),
+                    category: p.categories.into_iter().next().map(|c| c.name),
+                    address: p.address.filter(|a| !a.is_empty()),
+                }))
+                .map_err(|e| WhatsAppError::QueryFailed(e.to_string()));
+            let _ = reply.send(result);
+        }
+        BotCommand::GetProfilePicture { jid, reply } => {
+            log_event(window, "info", "Processing GetProfilePicture command");
+            let result = match client.contacts().get_profile_picture(&jid, false).await {
+                Ok(picture) => Ok(picture.map(|p| p.url)),
+                Err(e) => {
+                    // No picture and hidden-by-privacy both come back as errors; neither is a failure
+                    let error = anyhow::Error::from(e);
+                    let no_picture = match classify(&error) {
+                        Failure::Status(code) => matches!(code, 401 | 404),
+                        Failure::Unrecognised => mentions_any(window, &error, &["item-not-found", "not-authorized"]),
+                        Failure::Connection | Failure::Timeout => false,
+                    };
+                    if no_picture {
+                        Ok(None)
+                    } else {
+                        Err(WhatsAppError::QueryFailed(error.to_string()))
+                    }
+                }
+            };
+            let _ = reply.send(result);
+        }
+        BotCommand::Ping { reply } => {
+            let _ = reply.send(Ok(()));
+        }
+        BotCommand::Wake => {
+            // Already connected; a second caller woke the task at the same time
+        }
+        BotCommand::Logout { .. } | BotCommand::Shutdown { .. } => {
+            // Handled by the bot loop since they end the session
+        }
+    }
+}
+
+// Tauri Command: Check if bot is ready
+#[tauri::command]
+pub async fn is_bot_ready(
+    account_id: String,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<bool, WhatsAppError> {
+    let Some(session) = state.find_session(&account_id).await else {
+        return Ok(false);
+    };
+    // An idle session reconnects on the next command, so it still counts as ready
+    let is_ready = *session.is_ready.lock().await || *session.is_idle.lock().await;
+    Ok(is_ready)
+}
+
+// Tauri Command: Check the bot task is actually processing commands, not just flagged ready
+#[tauri::command]
+pub async fn ping_bot(
+    account_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<bool, WhatsAppError> {
+    let Some(session) = state.find_session(&account_id).await else {
+        return Ok(false);
+    };
+    
+    let ping = session.dispatch(|reply| BotCommand::Ping { reply });
+    match tokio::time::timeout(PING_TIMEOUT, ping).await {
+        Ok(result) => result.map(|_| true),
+        Err(_) => {
+            log_event(&window, "warn", format!("Bot task did not answer ping within {:?}", PING_TIMEOUT));
+            Ok(false)
+        }
+    }
+}
+
+// Tauri Command: Get the current connection status
+#[tauri::command]
+pub async fn connection_status(
+    account_id: String,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<ConnectionStatus, WhatsAppError> {
+    let Some(session) = state.find_session(&account_id).await else {
+        return Ok(ConnectionStatus::Uninitialized);
+    };
+    let has_channel = session.command_tx.lock().await.is_some();
+    let is_authenticated = *session.is_authenticated.lock().await;
+    let is_ready = *session.is_ready.lock().await;
+    let status = *session.status.lock().await;
+
+    // The flags are authoritative; the tracked status only fills in the gaps between them
+    let status = match status {
+        ConnectionStatus::LoggedOut if !is_authenticated => ConnectionStatus::LoggedOut,
+        _ if !has_channel => ConnectionStatus::Uninitialized,
+        _ if is_ready => ConnectionStatus::Connected,
+        _ if is_authenticated => ConnectionStatus::Authenticated,
+        other => other,
+    };
+    Ok(status)
+}
+
+// Tauri Command: Get the phone number of the logged in account
+#[tauri::command]
+pub async fn get_own_number(
+    account_id: String,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<String>, WhatsAppError> {
+    let Some(session) = state.find_session(&account_id).await else {
+        return Ok(None);
+    };
+    let self_jid = session.self_jid.lock().await.clone();
+    Ok(self_jid.map(|jid| jid.split('@').next().unwrap_or_default().to_string()))
+}
+
+// Tauri Command: Free the in-memory caches of a long-running session, keeping the
+// keep_last most recent entries of each (default 0). The connection, the stored
+// session and the chat history are left alone.
+#[tauri::command]
+pub async fn clear_caches(
+    account_id: String,
+    keep_last: Option<usize>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    let keep = keep_last.unwrap_or(0);
+    
+    log_event(&window, "info", format!("Clearing caches, keeping the last {} entries", keep));
+    
+    session.media_cache.lock().await.retain_last(keep);
+    session.forwardable.lock().await.retain_last(keep);
+    session.seen_message_ids.lock().await.retain_last(keep);
+    
+    // Statuses are only tracked for our own sends, so they follow sent_messages
+    {
+        let mut sent = session.sent_messages.lock().await;
+        if sent.len() > keep {
+            let mut by_age: Vec<(String, i64)> = sent.iter().map(|(id, m)| (id.clone(), m.sent_at_ms)).collect();
+            by_age.sort_by_key(|(_, sent_at_ms)| std::cmp::Reverse(*sent_at_ms));
+            for (id, _) in by_age.into_iter().skip(keep) {
+                sent.remove(&id);
+            }
+        }
+        session.message_statuses.lock().await.retain(|id, _| sent.contains_key(id));
+    }
+    
+    // Lookup caches refill on demand
+    session.profile_pictures.lock().await.clear();
+    session.unregistered_numbers.lock().await.clear();
+    Ok(())
+}
+
+// Tauri Command: Get the QR code currently waiting to be scanned, for a UI that
+// started listening after the "qr-code" event had already fired
+#[tauri::command]
+pub async fn get_current_qr(
+    account_id: String,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<String>, WhatsAppError> {
+    let Some(session) = state.find_session(&account_id).await else {
+        return Ok(None);
+    };
+    let current_qr = session.current_qr.lock().await.clone();
+    Ok(current_qr)
+}
+
+// Tauri Command: Get the delivery status of a message sent from this app
+#[tauri::command]
+pub async fn get_message_status(
+    account_id: String,
+    message_id: String,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<MessageStatus>, WhatsAppError> {
+    let Some(session) = state.find_session(&account_id).await else {
+        return Ok(None);
+    };
+    let status = session.message_statuses.lock().await.get(&message_id).copied();
+    Ok(status)
+}
+
+// Tauri Command: Log out and tear down the bot task
+#[tauri::command]
+pub async fn logout(
+    account_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    // Without a live session the bot task can't reply, so fail fast instead of waiting
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    log_event(&window, "info", "Logging out");
+    
+    match session.dispatch(|reply| BotCommand::Logout { reply }).await {
+        Ok(()) => {
+            log_event(&window, "info", "Logged out successfully");
+            Ok(())
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to log out: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Close the connection but keep the session for a later init_whatsapp
+#[tauri::command]
+pub async fn disconnect(
+    account_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    
+    log_event(&window, "info", "Disconnecting");
+    
+    match session.dispatch(|reply| BotCommand::Shutdown { reply }).await {
+        Ok(()) => {
+            log_event(&window, "info", "Disconnected successfully");
+            Ok(())
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to disconnect: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Delete the stored session so the next init_whatsapp starts a fresh QR flow.
+// The bot must be stopped first; an open database can't be deleted on Windows.
+#[tauri::command]
+pub async fn reset_session(
+    account_id: String,
+    db_name: Option<String>, // Only needed if init_whatsapp hasn't run for this account yet
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session_or_insert(&account_id).await?;
+    if session.command_tx.lock().await.is_some() || *session.is_initializing.lock().await {
+        return Err(WhatsAppError::SessionActive);
+    }
+    
+    let recorded_path = session.db_path.lock().await.clone();
+    let db_path = match recorded_path {
+        Some(path) => path,
+        None => resolve_storage_dir(&window, &session)?.join(database_file_name(db_name, &account_id)?),
+    };
+    
+    // command_tx is cleared before the bot task finishes unwinding, so the store (and
+    // with it SQLite's handle on the file) can briefly outlive a stopped session
+    let store = session.store.lock().await.clone();
+    let deadline = tokio::time::Instant::now() + STORE_RELEASE_TIMEOUT;
+    while store.strong_count() > 0 {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(WhatsAppError::DatabaseLocked(db_path.display().to_string()));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    
+    log_event(&window, "info", format!("Resetting session, deleting: {}", db_path.display()));
+    remove_db_files(&db_path).await?;
+    if let Err(e) = std::fs::remove_file(history_file(&db_path)) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(WhatsAppError::FileWrite(e.to_string()));
+        }
+    }
+    
+    // Dropping the session clears every flag and cache; the next init creates a new one
+    state.accounts.lock().await.remove(&account_id);
+    log_event(&window, "info", format!("Session for '{}' reset", account_id));
+    Ok(())
+}
+
+// Tauri Command: Send text message
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn send_message(
+    account_id: String,
+    contact: String,
+    message: String,
+    quoted_message_id: Option<String>,
+    quoted_sender: Option<String>,
+    is_group: Option<bool>,
+    mentions: Option<Vec<String>>, // Phone numbers of tagged users
+    queue_if_offline: Option<bool>, // Hold the message until the connection is back instead of failing
+    retries: Option<u32>, // Extra attempts after network errors; validation errors never retry
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<SendResult, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    let queue_if_offline = queue_if_offline.unwrap_or(false);
+    if !queue_if_offline {
+        session.ensure_ready().await?;
+    }
+    
+    log_event(&window, "info", format!("Sending message to contact: {}", contact));
+    
+    let (jid, wa_message) = text_send(&contact, is_group.unwrap_or(false), &message)?;
+    log_event(&window, "info", format!("Parsed JID: {}", jid));
+    // Our own number is the "Message yourself" chat. It's addressed by the bare JID, which
+    // reaches all our devices; a device JID would only reach that one device.
+    let jid = if session.is_self(&jid).await {
+        log_event(&window, "info", "Sending to own number, using the \"Message yourself\" chat");
+        Jid::new(&jid.user, &jid.server)
+    } else {
+        jid
+    };
+    
+    let mut context = session.quote_context(quoted_message_id, quoted_sender).await;
+    
+    let mentioned_jids = mention_jids(&window, &mentions.unwrap_or_default());
+    if !mentioned_jids.is_empty() {
+        context.get_or_insert_with(Default::default).mentioned_jid = mentioned_jids;
+    }
+    
+    let resolved_jid = jid.to_string();
+    let normalized_contact = jid.user.clone();
+    
+    if queue_if_offline && !*session.is_ready.lock().await {
+        let local_id = queue_text(&session, jid, wa_message, message, context, window).await;
+        return Ok(SendResult {
+            message_id: Some(local_id),
+            jid: resolved_jid,
+            normalized_contact,
+            queued: true,
+            dry_run: None,
+        });
+    }
+    
+    let delivery = send_text(&window, &session, jid, wa_message, &message, context, retries.unwrap_or(0)).await?;
+    let (message_id, dry_run) = match delivery {
+        Delivery::Sent(message_id) => (Some(message_id), None),
+        Delivery::DryRun(preview) => (None, Some(preview)),
+    };
+    
+    Ok(SendResult {
+        message_id,
+        jid: resolved_jid,
+        normalized_contact,
+        queued: false,
+        dry_run,
+    })
+}
+
+// Tauri Command: Send the same text message to several contacts, one after another
+#[tauri::command]
+pub async fn send_message_bulk(
+    account_id: String,
+    contacts: Vec<String>,
+    message: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Vec<(String, Result<Option<String>, WhatsAppError>)>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    if message.trim().is_empty() {
+        return Err(WhatsAppError::EmptyMessage);
+    }
+    
+    // "+91 98765 43210" and "919876543210" are the same person; only message them once.
+    // Valid entries are reported by their normalized number, invalid ones as given.
+    let mut seen = HashSet::new();
+    let mut recipients = Vec::with_capacity(contacts.len());
+    for contact in contacts {
+        match contact_to_jid(&contact, false) {
+            Ok(jid) => {
+                if seen.insert(jid.to_string()) {
+                    recipients.push((jid.user.clone(), Ok(jid)));
+                }
+            }
+            Err(e) => recipients.push((contact, Err(e))),
+        }
+    }
+    
+    log_event(&window, "info", format!("Sending bulk message to {} contact(s)", recipients.len()));
+    
+    // Sequential on purpose: one in-flight send at a time keeps the connection calm,
+    // and a failure for one contact never stops the rest
+    let total = recipients.len();
+    let mut results = Vec::with_capacity(total);
+    for (index, (contact, jid)) in recipients.into_iter().enumerate() {
+        let result = match jid {
+            Ok(jid) => send_text(&window, &session, jid, text_message(&message), &message, None, 0).await
+                .map(Delivery::into_message_id),
+            Err(e) => Err(e),
+        };
+        session.emit(&window, "bulk-progress", BulkProgressEvent {
+            index,
+            total,
+            contact: contact.clone(),
+            status: match &result {
+                Ok(Some(_)) => "sent",
+                Ok(None) => "dry-run",
+                Err(_) => "failed",
+            },
+            message_id: result.as_ref().ok().cloned().flatten(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        results.push((contact, result));
+    }
+    
+    Ok(results)
+}
+
+// Shared send path for plain text messages; `preview` is the text kept in the chat history
+async fn send_text(
+    window: &Window,
+    session: &AccountSession,
+    jid: Jid,
+    wa_message: wa::Message,
+    preview: &str,
+    context: Option<wa::ContextInfo>,
+    retries: u32,
+) -> Result<Delivery<String>, WhatsAppError> {
+    session.acquire_send_slot().await?;
+    log_event(window, "info", format!("Attempting to send message: {}", preview));
+    
+    // Send command to bot task via channel (avoids cross-thread Rc crash)
+    let chat_jid = jid.clone();
+    let sink = session.command_sink().await?;
+    let result = route_text(&sink, session.command_timeout, jid, wa_message, context, retries).await;
+    
+    match result {
+        Ok(Delivery::Sent(msg_id)) => {
+            log_event(window, "info", format!("Message sent successfully with ID: {}", msg_id));
+            session.record_sent(window, &msg_id, &chat_jid, preview).await;
+            Ok(Delivery::Sent(msg_id))
+        }
+        Ok(dry_run) => Ok(dry_run),
+        Err(e) => {
+            log_event(window, "error", format!("Failed to send message: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Send text to a fully qualified JID with no normalization, for LIDs,
+// newsletters ("@newsletter") or status updates ("status@broadcast"). Returns no id in dry run.
+#[tauri::command]
+pub async fn send_raw(
+    account_id: String,
+    jid: String, // Must include the server, e.g. "120363012345678901@newsletter"
+    message: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<String>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    if message.trim().is_empty() {
+        return Err(WhatsAppError::EmptyMessage);
+    }
+    let jid = raw_jid(&jid)?;
+    log_event(&window, "info", format!("Sending raw message to: {}", jid));
+    
+    send_text(&window, &session, jid, text_message(&message), &message, None, 0).await
+        .map(Delivery::into_message_id)
+}
+
+// Holds a text message until Event::Connected flushes the queue, returning a local
+// tracking id. The real outcome arrives later as a "queued-message-result" event.
+async fn queue_text(
+    session: &Arc<AccountSession>,
+    jid: Jid,
+    wa_message: wa::Message,
+    preview: String,
+    context: Option<wa::ContextInfo>,
+    window: Window,
+) -> String {
+    let local_id = format!("queued-{}", NEXT_QUEUED_ID.fetch_add(1, Ordering::Relaxed));
+    let (reply_tx, reply_rx) = oneshot::channel();
+    
+    {
+        let mut pending = session.pending.lock().await;
+        if pending.len() >= MAX_PENDING_MESSAGES {
+            if let Some(BotCommand::SendMessage { reply, .. }) = pending.pop_front() {
+                let _ = reply.send(Err(WhatsAppError::QueueFull));
+            }
+        }
+        pending.push_back(BotCommand::SendMessage {
+            jid: jid.clone(),
+            message: wa_message,
+            context,
+            retries: 0,
+            reply: reply_tx,
+        });
+        log_event(&window, "info", format!("Queued message {} for {} ({} pending)", local_id, jid, pending.len()));
+    }
+    
+    // An idle session only reconnects when asked; Event::Connected then flushes the queue
+    if *session.is_idle.lock().await {
+        let _ = session.request_wake().await;
+    }
+    
+    let session = session.clone();
+    let tracking_id = local_id.clone();
+    tokio::spawn(async move {
+        let result = reply_rx.await.unwrap_or(Err(WhatsAppError::BotTaskGone));
+        let (message_id, error, dry_run) = match result {
+            Ok(Delivery::Sent(msg_id)) => {
+                log_event(&window, "info", format!("Queued message {} sent with ID: {}", tracking_id, msg_id));
+                session.record_sent(&window, &msg_id, &jid, &preview).await;
+                (Some(msg_id), None, None)
+            }
+            // Dry run was turned on while it waited; the message also went to "dry-run-message"
+            Ok(Delivery::DryRun(preview)) => (None, None, Some(preview)),
+            Err(e) => {
+                log_event(&window, "error", format!("Queued message {} failed: {}", tracking_id, e));
+                (None, Some(e.to_string()), None)
+            }
+        };
+        session.emit(&window, "queued-message-result", QueuedMessageEvent {
+            local_id: tracking_id,
+            message_id,
+            error,
+            dry_run,
+        });
+    });
+    
+    local_id
+}
+
+// Tauri Command: Send message with media
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn send_media_message(
+    account_id: String,
+    contact: String,
+    message_text: String,
+    media_path: String,
+    media_type: String, // "image", "video", "audio", "document"
+    ptt: Option<bool>, // Send audio as a voice note
+    is_group: Option<bool>,
+    view_once: Option<bool>, // Image or video the recipient can open only once
+    gif_playback: Option<bool>, // Video plays as a looping GIF, e.g. an mp4 reaction GIF
+    upload_id: Option<String>, // Caller-chosen id for cancel_upload and upload-progress
+    quoted_message_id: Option<String>,
+    quoted_sender: Option<String>,
+    thumbnail_path: Option<String>, // JPEG preview, ideally ~100px on the longest side
+    file_name: Option<String>, // Name shown for a document, e.g. "Invoice.pdf"; also picks the MIME type
+    caption: Option<String>, // Takes precedence over message_text
+    mime_override: Option<String>, // e.g. "image/png" when the extension is missing or misleading
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<MediaSendResult, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    let view_once = view_once.unwrap_or(false);
+    if view_once && !matches!(media_type.as_str(), "image" | "video") {
+        return Err(WhatsAppError::ViewOnceUnsupported(media_type));
+    }
+    let gif_playback = gif_playback.unwrap_or(false);
+    if gif_playback && media_type != "video" {
+        return Err(WhatsAppError::GifPlaybackUnsupported(media_type));
+    }
+    let mime_override = mime_override
+        .filter(|mime| !mime.trim().is_empty())
+        .map(|mime| validate_mime(&mime))
+        .transpose()?;
+    
+    log_event(&window, "info", format!("Sending {} to: {}", media_type, jid));
+    
+    // Check the size first so an oversized file is never loaded into memory
+    let (media_path, file_size) = check_input_file(&media_path)?;
+    state.media_limits.lock().await.check(&media_type, file_size)?;
+    
+    let media_data = read_input_file(&media_path)?;
+    log_event(&window, "info", format!("Read media file: {} bytes", media_data.len()));
+    
+    // The file on disk may have a temporary name; the recipient sees the override instead
+    // Lossy rather than to_str() so a name that isn't valid UTF-8 keeps its readable parts
+    let file_name = sanitize_file_name(&file_name
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| {
+            media_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        }));
+    
+    // Without a preview some clients show a blank box until the media is downloaded
+    let jpeg_thumbnail = match thumbnail_path {
+        Some(path) => {
+            let (thumbnail_path, _) = check_input_file(&path)?;
+            let thumbnail = read_input_file(&thumbnail_path)?;
+            if !thumbnail.starts_with(&[0xFF, 0xD8, 0xFF]) {
+                return Err(WhatsAppError::InvalidThumbnail(path));
+            }
+            Some(thumbnail)
+        }
+        None if media_type == "image" => generate_thumbnail(&window, &media_data),
+        None => None,
+    };
+    
+    let media = OutgoingMedia {
+        data: media_data,
+        media_category: media_type,
+        file_name,
+        caption: caption.unwrap_or(message_text),
+        ptt: ptt.unwrap_or(false),
+        view_once,
+        gif_playback,
+        jpeg_thumbnail,
+        // Only attached once the upload succeeds, so a failed upload sends nothing at all
+        context: session.quote_context(quoted_message_id, quoted_sender).await,
+        upload_id,
+        mime_override,
+    };
+    send_media(&state, &session, jid, media, window).await
+}
+
+// Tauri Command: Post a status (story) to status@broadcast: either text on a coloured
+// background, or an image or video with an optional caption
+#[tauri::command]
+pub async fn post_status(
+    account_id: String,
+    text: Option<String>,
+    media_path: Option<String>,
+    background_color: Option<u32>, // ARGB for text statuses; 0xRRGGBB is taken as opaque
+    caption: Option<String>, // Media statuses only
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let text = text.filter(|t| !t.trim().is_empty());
+    let media_path = media_path.filter(|p| !p.trim().is_empty());
+    let content = match (text, media_path) {
+        (Some(_), Some(_)) => {
+            return Err(WhatsAppError::InvalidStatus("give either text or media_path, not both".to_string()));
+        }
+        (None, None) => {
+            return Err(WhatsAppError::InvalidStatus("give text or media_path".to_string()));
+        }
+        (Some(text), None) => {
+            if caption.is_some() {
+                return Err(WhatsAppError::InvalidStatus("caption only applies to media statuses".to_string()));
+            }
+            let color = background_color.unwrap_or(DEFAULT_STATUS_BACKGROUND);
+            // Without an alpha byte the background would be fully transparent
+            let background_argb = if color <= 0x00FF_FFFF { color | 0xFF00_0000 } else { color };
+            StatusContent::Text { text, background_argb }
+        }
+        (None, Some(path)) => {
+            if background_color.is_some() {
+                return Err(WhatsAppError::InvalidStatus("background_color only applies to text statuses".to_string()));
+            }
+            let (resolved_path, file_size) = check_input_file(&path)?;
+            let file_name = resolved_path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let extension = resolved_path
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let media_category = match extension.as_str() {
+                "jpg" | "jpeg" | "png" | "webp" => "image",
+                "mp4" | "mov" | "mkv" | "avi" => "video",
+                _ => {
+                    return Err(WhatsAppError::InvalidStatus(format!(
+                        "'{}' isn't an image or video; statuses can't hold other media",
+                        file_name
+                    )));
+                }
+            };
+            state.media_limits.lock().await.check(media_category, file_size)?;
+            let data = read_input_file(&resolved_path)?;
+            if data.is_empty() {
+                return Err(WhatsAppError::EmptyMedia);
+            }
+            let (media_type_enum, mime_type) = get_media_type_and_mime(media_category, &file_name);
+            let jpeg_thumbnail = if media_category == "image" { generate_thumbnail(&window, &data) } else { None };
+            StatusContent::Media {
+                data,
+                media_type_enum,
+                media_category: media_category.to_string(),
+                mime_type,
+                caption: caption.unwrap_or_default(),
+                jpeg_thumbnail,
+            }
+        }
+    };
+    
+    log_event(&window, "info", "Posting status update");
+    
+    let result = session.dispatch_within(state.media_timeout, |reply| BotCommand::PostStatus {
+        content,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Status posted with ID: {}", msg_id));
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to post status: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Send a WebP file (static or animated) as a sticker
+#[tauri::command]
+pub async fn send_sticker(
+    account_id: String,
+    contact: String,
+    sticker_path: String,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    log_event(&window, "info", format!("Sending sticker to: {}", jid));
+    
+    let (resolved_path, file_size) = check_input_file(&sticker_path)?;
+    state.media_limits.lock().await.check("image", file_size)?;
+    
+    let media_data = read_input_file(&resolved_path)?;
+    
+    // Check the bytes rather than the extension; a renamed PNG would be rejected by WhatsApp
+    if !is_webp(&media_data) {
+        return Err(WhatsAppError::InvalidSticker(sticker_path));
+    }
+    let is_animated = is_animated_webp(&media_data);
+    
+    let chat_jid = jid.clone();
+    let result = session.dispatch_within(state.media_timeout, |reply| BotCommand::SendSticker {
+        jid,
+        media_data,
+        is_animated,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Sticker sent successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, "[sticker]").await;
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send sticker: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Send media held in memory (e.g. a canvas export) as base64.
+// Returns no id in dry run.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn send_media_bytes(
+    account_id: String,
+    contact: String,
+    message_text: String,
+    data_base64: String,
+    media_type: String, // "image", "video", "audio", "document"
+    file_name: String, // Used for the MIME type and document name
+    ptt: Option<bool>,
+    is_group: Option<bool>,
+    upload_id: Option<String>, // Caller-chosen id for cancel_upload and upload-progress
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<String>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    log_event(&window, "info", format!("Sending in-memory {} to: {}", media_type, jid));
+    
+    let media_data = base64::engine::general_purpose::STANDARD
+        .decode(data_base64.trim())
+        .map_err(|e| WhatsAppError::InvalidBase64(e.to_string()))?;
+    log_event(&window, "info", format!("Decoded media: {} bytes", media_data.len()));
+    state.media_limits.lock().await.check(&media_type, media_data.len() as u64)?;
+    
+    let media = OutgoingMedia {
+        data: media_data,
+        media_category: media_type,
+        file_name: sanitize_file_name(&file_name),
+        caption: message_text,
+        ptt: ptt.unwrap_or(false),
+        view_once: false,
+        gif_playback: false,
+        context: None,
+        jpeg_thumbnail: None,
+        upload_id,
+        mime_override: None,
+    };
+    send_media(&state, &session, jid, media, window).await.map(|sent| sent.message_id)
+}
+
+// Shared send path for media messages, whatever the bytes came from
+async fn send_media(
+    state: &WhatsAppState,
+    session: &AccountSession,
+    jid: Jid,
+    media: OutgoingMedia,
+    window: Window,
+) -> Result<MediaSendResult, WhatsAppError> {
+    let caption = media_caption(&media.data, &media.caption)?;
+    
+    let (media_type_enum, guessed_mime) = get_media_type_and_mime(&media.media_category, &media.file_name);
+    let mime_type = media.mime_override.clone().unwrap_or(guessed_mime);
+    
+    let upload_id = media.upload_id.unwrap_or_else(|| format!("upload-{}", now_millis()));
+    let preview = if caption.is_empty() {
+        format!("[{}]", media.media_category)
+    } else {
+        caption.clone()
+    };
+    let cancel = CancellationToken::new();
+    state.uploads.lock().await.insert(upload_id.clone(), cancel.clone());
+    
+    // Copies for the reuse token; the command takes the originals
+    let token_category = media.media_category.clone();
+    let token_mime = mime_type.clone();
+    let token_file_name = media.file_name.clone();
+    let token_thumbnail = media.jpeg_thumbnail.clone();
+    
+    // Send command to bot task via channel (avoids cross-thread Rc crash)
+    let chat_jid = jid.clone();
+    let result = session.dispatch_within(state.media_timeout, |reply| BotCommand::SendMediaMessage {
+        jid,
+        media_data: media.data,
+        media_type_enum,
+        media_category: media.media_category,
+        mime_type,
+        caption,
+        file_name: media.file_name,
+        ptt: media.ptt,
+        view_once: media.view_once,
+        gif_playback: media.gif_playback,
+        context: media.context,
+        jpeg_thumbnail: media.jpeg_thumbnail,
+        upload_id: upload_id.clone(),
+        cancel,
+        window: window.clone(),
+        reply,
+    }).await;
+    
+    state.uploads.lock().await.remove(&upload_id);
+    
+    match result {
+        Ok(Delivery::Sent((msg_id, upload))) => {
+            log_event(&window, "info", format!("Media message sent successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, &preview).await;
+            let reuse_token = ReuseToken {
+                media_category: token_category,
+                mime_type: token_mime,
+                file_name: token_file_name,
+                jpeg_thumbnail: token_thumbnail,
+                upload: upload.clone(),
+                created_at: now_millis(),
+            }.encode();
+            let engine = base64::engine::general_purpose::STANDARD;
+            Ok(MediaSendResult {
+                message_id: Some(msg_id),
+                upload: Some(MediaUploadResult {
+                    url: upload.url,
+                    direct_path: upload.direct_path,
+                    media_key: engine.encode(&upload.media_key),
+                    file_sha256: engine.encode(&upload.file_sha256),
+                    file_enc_sha256: engine.encode(&upload.file_enc_sha256),
+                    file_length: upload.file_length,
+                }),
+                reuse_token: Some(reuse_token),
+                dry_run: None,
+            })
+        }
+        Ok(Delivery::DryRun(preview)) => Ok(MediaSendResult {
+            message_id: None,
+            upload: None,
+            reuse_token: None,
+            dry_run: Some(preview),
+        }),
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send media message: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Send media uploaded earlier by send_media_message again, without
+// uploading it, using the reuse_token from that send. Returns no id in dry run.
+#[tauri::command]
+pub async fn send_cached_media(
+    account_id: String,
+    contact: String,
+    reuse_token: String,
+    media_type: String, // Must match the type the file was first sent as
+    caption: Option<String>,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<String>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    let token = ReuseToken::decode(&reuse_token)?;
+    // The media key was derived for the original type, so another type can't decrypt it
+    if token.media_category != media_type {
+        return Err(WhatsAppError::InvalidReuseToken(format!(
+            "token is for {}, not {}", token.media_category, media_type
+        )));
+    }
+    
+    log_event(&window, "info", format!("Sending cached {} to: {}", media_type, jid));
+    let caption = caption.unwrap_or_default();
+    let preview = if caption.is_empty() { format!("[{}]", media_type) } else { caption.clone() };
+    let message = media_message(
+        &token.media_category, token.upload, token.mime_type, caption, token.file_name,
+        false, false, false, None, token.jpeg_thumbnail,
+    );
+    
+    session.acquire_send_slot().await?;
+    let chat_jid = jid.clone();
+    let result = session.dispatch(|reply| BotCommand::SendMessage {
+        jid,
+        message,
+        context: None,
+        retries: 0,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(Delivery::Sent(msg_id)) => {
+            log_event(&window, "info", format!("Cached media sent successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, &preview).await;
+            Ok(Some(msg_id))
+        }
+        Ok(Delivery::DryRun(_)) => Ok(None),
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send cached media: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Abort a media upload started with the given upload id
+#[tauri::command]
+pub async fn cancel_upload(
+    upload_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<bool, WhatsAppError> {
+    match state.uploads.lock().await.get(&upload_id) {
+        Some(cancel) => {
+            log_event(&window, "info", format!("Cancelling upload {}", upload_id));
+            cancel.cancel();
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Tauri Command: Override the per-type media size limits
+#[tauri::command]
+pub async fn set_media_limits(
+    limits: MediaLimits,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    *state.media_limits.lock().await = limits;
+    state.save_settings(&window).await;
+    Ok(())
+}
+
+// Tauri Command: Cap text sends across all accounts at per_minute (0 turns the limit off).
+// mode "wait" delays sends over the limit; "reject" fails them with RateLimited.
+#[tauri::command]
+pub async fn configure_rate_limit(
+    per_minute: u32,
+    mode: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let mode = RateLimitMode::parse(&mode)?;
+    
+    let limiter = if per_minute == 0 {
+        log_event(&window, "info", "Rate limit disabled");
+        None
+    } else {
+        log_event(&window, "info", format!("Rate limit set to {} messages per minute", per_minute));
+        Some(RateLimiter::new(per_minute, mode))
+    };
+    *state.rate_limiter.lock().await = limiter;
+    state.save_settings(&window).await;
+    Ok(())
+}
+
+// Tauri Command: Only forward the named events (without the "::account" suffix) to the
+// frontend, e.g. ["message-received"]. An empty list forwards everything again.
+#[tauri::command]
+pub async fn set_event_filter(
+    events: Vec<String>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let filter = if events.is_empty() {
+        log_event(&window, "info", "Event filter cleared");
+        None
+    } else {
+        log_event(&window, "info", format!("Event filter set to: {:?}", events));
+        Some(events.into_iter().collect())
+    };
+    *state.event_filter.write().unwrap_or_else(|e| e.into_inner()) = filter;
+    state.save_settings(&window).await;
+    Ok(())
+}
+
+// Tauri Command: Turn the "debug-log" event stream on or off
+#[tauri::command]
+pub async fn set_debug_logging(
+    enabled: bool,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    DEBUG_LOGGING.store(enabled, Ordering::Relaxed);
+    log_event(&window, "info", format!("Debug logging {}", if enabled { "enabled" } else { "disabled" }));
+    state.save_settings(&window).await;
+    Ok(())
+}
+
+// Tauri Command: Turn the "raw-event" stream of every protocol event on or off
+#[tauri::command]
+pub async fn enable_raw_events(
+    enabled: bool,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    RAW_EVENTS.store(enabled, Ordering::Relaxed);
+    log_event(&window, "info", format!("Raw events {}", if enabled { "enabled" } else { "disabled" }));
+    state.save_settings(&window).await;
+    Ok(())
+}
+
+// Tauri Command: Disconnect (without logging out) after this many minutes with no commands,
+// emitting "idle-disconnected"; the next command reconnects first. 0 means never.
+#[tauri::command]
+pub async fn set_idle_timeout(
+    minutes: u32,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    IDLE_TIMEOUT_SECS.store(u64::from(minutes) * 60, Ordering::Relaxed);
+    if minutes == 0 {
+        log_event(&window, "info", "Idle disconnect disabled");
+    } else {
+        log_event(&window, "info", format!("Disconnecting after {} idle minute(s)", minutes));
+    }
+    state.save_settings(&window).await;
+    Ok(())
+}
+
+// Tauri Command: Build text and media messages without sending them. Each send reports
+// the message it would have sent as a "dry-run-message" event; send_message and
+// send_media_message also return it as `dry_run`, with no message id. Media isn't uploaded,
+// nothing is added to the history and no reuse token is issued. Other send commands are
+// unaffected.
+#[tauri::command]
+pub async fn set_dry_run(
+    enabled: bool,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    state.dry_run.store(enabled, Ordering::Relaxed);
+    log_event(&window, "info", format!("Dry run {}", if enabled { "enabled" } else { "disabled" }));
+    Ok(())
+}
+
+// Tauri Command: Read the settings made through the commands above
+#[tauri::command]
+pub async fn get_settings(
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Settings, WhatsAppError> {
+    Ok(state.current_settings().await)
+}
+
+// Tauri Command: Put every setting back to its default and delete the settings file
+#[tauri::command]
+pub async fn reset_settings(
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    state.apply_settings(Settings::default()).await;
+    if let Some(path) = settings_file(window.app_handle()) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(WhatsAppError::FileWrite(e.to_string()));
+            }
+        }
+    }
+    log_event(&window, "info", "Settings reset to defaults");
+    Ok(())
+}
+
+// Tauri Command: Share a contact card. Pass a full vCard, or just a phone number
+// and one is generated from it and display_name.
+#[tauri::command]
+pub async fn send_contact(
+    account_id: String,
+    contact: String,
+    display_name: String,
+    vcard: Option<String>,
+    phone: Option<String>, // Phone number of the shared contact, used when vcard is omitted
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    let vcard = match (vcard, phone) {
+        (Some(vcard), _) if !vcard.trim().is_empty() => vcard,
+        (_, Some(phone)) => build_vcard(&display_name, &normalize_phone(&phone)?),
+        _ => {
+            return Err(WhatsAppError::InvalidContact(
+                "send_contact needs a vcard or a phone number".to_string(),
+            ))
+        }
+    };
+    
+    log_event(&window, "info", format!("Sending contact card '{}' to: {}", display_name, jid));
+    let preview = format!("[contact] {}", display_name);
+    
+    let wa_message = wa::Message {
+        contact_message: Some(Box::new(wa::message::ContactMessage {
+            display_name: Some(display_name),
+            vcard: Some(vcard),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    
+    let chat_jid = jid.clone();
+    let result = session.dispatch(|reply| BotCommand::SendContact {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Contact card sent successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, &preview).await;
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send contact card: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Forward a recent message (sent or received) to another chat.
+// Media is re-sent by reference, so nothing is downloaded or uploaded again.
+#[tauri::command]
+pub async fn forward_message(
+    account_id: String,
+    source_message_id: String,
+    target_contact: String,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&target_contact, is_group.unwrap_or(false))?;
+    
+    let message = session
+        .forwardable
+        .lock()
+        .await
+        .get(&source_message_id)
+        .and_then(forwarded_copy)
+        .ok_or_else(|| WhatsAppError::ForwardUnavailable(source_message_id.clone()))?;
+    
+    log_event(&window, "info", format!("Forwarding message {} to: {}", source_message_id, jid));
+    let preview = message_preview(&message).unwrap_or_else(|| "[forwarded]".to_string());
+    
+    let chat_jid = jid.clone();
+    let result = session.dispatch(|reply| BotCommand::ForwardMessage {
+        jid,
+        message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Message forwarded successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, &preview).await;
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to forward message: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Send a message with up to 3 quick-reply buttons. Only business
+// accounts can send these; taps arrive as "button-reply" events.
+#[tauri::command]
+pub async fn send_buttons(
+    account_id: String,
+    contact: String,
+    body: String,
+    buttons: Vec<ButtonDef>,
+    footer: Option<String>,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    if body.trim().is_empty() {
+        return Err(WhatsAppError::EmptyMessage);
+    }
+    if !(1..=3).contains(&buttons.len()) {
+        return Err(WhatsAppError::InvalidButtons(format!("needs 1 to 3 buttons, got {}", buttons.len())));
+    }
+    if buttons.iter().any(|b| b.id.trim().is_empty() || b.text.trim().is_empty()) {
+        return Err(WhatsAppError::InvalidButtons("every button needs an id and a text".to_string()));
+    }
+    if buttons.iter().map(|b| &b.id).collect::<HashSet<_>>().len() != buttons.len() {
+        return Err(WhatsAppError::InvalidButtons("button ids must be distinct".to_string()));
+    }
+    
+    log_event(&window, "info", format!("Sending {} button(s) to: {}", buttons.len(), jid));
+    
+    let wa_message = wa::Message {
+        buttons_message: Some(Box::new(wa::message::ButtonsMessage {
+            content_text: Some(body.clone()),
+            footer_text: footer.filter(|f| !f.trim().is_empty()),
+            header_type: Some(wa::message::buttons_message::HeaderType::Empty as i32),
+            buttons: buttons
+                .into_iter()
+                .map(|b| wa::message::buttons_message::Button {
+                    button_id: Some(b.id),
+                    button_text: Some(wa::message::buttons_message::button::ButtonText {
+                        display_text: Some(b.text),
+                    }),
+                    r#type: Some(wa::message::buttons_message::button::Type::Response as i32),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    
+    let chat_jid = jid.clone();
+    let result = session.dispatch(|reply| BotCommand::SendButtons {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Buttons sent successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, &body).await;
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send buttons: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Start sharing a live location with a chat for duration_seconds (up to 8 hours).
+// The frontend feeds new coordinates through update_live_location; sharing a new live
+// location replaces the previous one.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn send_live_location(
+    account_id: String,
+    contact: String,
+    latitude: f64,
+    longitude: f64,
+    duration_seconds: u32,
+    caption: Option<String>,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    check_coordinates(latitude, longitude)?;
+    if !(1..=MAX_LIVE_LOCATION_SECS).contains(&duration_seconds) {
+        return Err(WhatsAppError::InvalidLocation(format!(
+            "duration must be between 1 and {} seconds",
+            MAX_LIVE_LOCATION_SECS
+        )));
+    }
+    
+    log_event(&window, "info", format!("Sharing live location with {} for {}s", jid, duration_seconds));
+    
+    let wa_message = live_location_message(latitude, longitude, 0, 0, caption);
+    let chat_jid = jid.clone();
+    let msg_id = session.dispatch(|reply| BotCommand::SendLocation {
+        jid,
+        message: wa_message,
+        reply,
+    }).await.map_err(|e| {
+        log_event(&window, "error", format!("Failed to send live location: {}", e));
+        e
+    })?;
+    session.record_sent(&window, &msg_id, &chat_jid, "[live location]").await;
+    
+    let started_at_ms = now_millis();
+    let stop = CancellationToken::new();
+    let generation = NEXT_LIVE_LOCATION_ID.fetch_add(1, Ordering::Relaxed);
+    let previous = state.live_locations.lock().await.insert(account_id.clone(), LiveLocation {
+        jid: chat_jid.clone(),
+        started_at_ms,
+        expires_at_ms: started_at_ms + duration_seconds as i64 * 1000,
+        sequence: 0,
+        generation,
+        stop: stop.clone(),
+    });
+    if let Some(previous) = previous {
+        previous.stop.cancel();
+    }
+    
+    // Ends the share on time; cancelled early if a newer live location replaces it
+    let live_locations = state.live_locations.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = stop.cancelled() => return,
+            _ = tokio::time::sleep(std::time::Duration::from_secs(duration_seconds as u64)) => {}
+        }
+        // A replacement may have landed between the sleep ending and taking the lock;
+        // only the share this timer was started for is removed
+        {
+            let mut live_locations = live_locations.lock().await;
+            if live_locations.get(&account_id).map(|live| live.generation) != Some(generation) {
+                return;
+            }
+            live_locations.remove(&account_id);
+        }
+        log_event(&window, "info", format!("Live location with {} ended", chat_jid));
+        session.emit(&window, "live-location-ended", LiveLocationEndedEvent {
+            jid: bare_jid(&chat_jid),
+        });
+    });
+    
+    Ok(msg_id)
+}
+
+// Tauri Command: Push new coordinates to the live location started by send_live_location
+#[tauri::command]
+pub async fn update_live_location(
+    account_id: String,
+    latitude: f64,
+    longitude: f64,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+    check_coordinates(latitude, longitude)?;
+    
+    let (jid, sequence, time_offset) = {
+        let mut live_locations = state.live_locations.lock().await;
+        let live = live_locations
+            .get_mut(&account_id)
+            .filter(|live| now_millis() < live.expires_at_ms)
+            .ok_or(WhatsAppError::NoLiveLocation)?;
+        live.sequence += 1;
+        let time_offset = ((now_millis() - live.started_at_ms) / 1000) as u32;
+        (live.jid.clone(), live.sequence, time_offset)
+    };
+    
+    let wa_message = live_location_message(latitude, longitude, sequence, time_offset, None);
+    let result = session.dispatch(|reply| BotCommand::SendLocation {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    if let Err(e) = &result {
+        log_event(&window, "error", format!("Failed to update live location: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Send a poll with 2-12 distinct options
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn send_poll(
+    account_id: String,
+    contact: String,
+    question: String,
+    options: Vec<String>,
+    allow_multiple: bool,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    let question = question.trim().to_string();
+    if question.is_empty() {
+        return Err(WhatsAppError::InvalidPoll("the question is empty".to_string()));
+    }
+    let options: Vec<String> = options.iter().map(|o| o.trim().to_string()).collect();
+    if !(2..=12).contains(&options.len()) {
+        return Err(WhatsAppError::InvalidPoll(format!("needs 2 to 12 options, got {}", options.len())));
+    }
+    if options.iter().any(|o| o.is_empty()) {
+        return Err(WhatsAppError::InvalidPoll("options cannot be empty".to_string()));
+    }
+    // Votes reference options by a hash of their text, so duplicates can't be told apart
+    if options.iter().collect::<HashSet<_>>().len() != options.len() {
+        return Err(WhatsAppError::InvalidPoll("options must be distinct".to_string()));
+    }
+    
+    log_event(&window, "info", format!("Sending poll '{}' with {} options to: {}", question, options.len(), jid));
+    let preview = format!("[poll] {}", question);
+    
+    let wa_message = wa::Message {
+        poll_creation_message: Some(Box::new(wa::message::PollCreationMessage {
+            name: Some(question),
+            options: options
+                .into_iter()
+                .map(|option_name| wa::message::poll_creation_message::Option {
+                    option_name: Some(option_name),
+                })
+                .collect(),
+            // 0 lets voters pick any number of options
+            selectable_options_count: Some(if allow_multiple { 0 } else { 1 }),
+            ..Default::default()
+        })),
+        // Voters encrypt their choices with this secret
+        message_context_info: Some(wa::MessageContextInfo {
+            message_secret: Some(rand::random::<[u8; 32]>().to_vec()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    
+    let chat_jid = jid.clone();
+    let result = session.dispatch(|reply| BotCommand::SendPoll {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Poll sent successfully with ID: {}", msg_id));
+            session.record_sent(&window, &msg_id, &chat_jid, &preview).await;
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send poll: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: React to a message (empty emoji removes the reaction)
+#[tauri::command]
+pub async fn send_reaction(
+    account_id: String,
+    contact: String,
+    message_id: String,
+    emoji: String,
+    from_me: Option<bool>, // Whether the target message was sent by us
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    log_event(&window, "info", format!("Reacting to {} in {} with '{}'", message_id, jid, emoji));
+    
+    let wa_message = wa::Message {
+        reaction_message: Some(wa::message::ReactionMessage {
+            key: Some(wa::MessageKey {
+                remote_jid: Some(jid.to_string()),
+                from_me: Some(from_me.unwrap_or(false)),
+                id: Some(message_id),
+                ..Default::default()
+            }),
+            text: Some(emoji),
+            sender_timestamp_ms: Some(now_millis()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    
+    let result = session.dispatch(|reply| BotCommand::SendReaction {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", format!("Reaction sent successfully with ID: {}", msg_id));
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to send reaction: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Turn disappearing messages on (seconds = 86400, 604800 or 7776000) or off (0)
+// for a chat. Later messages this app sends there carry the same timer.
+#[tauri::command]
+pub async fn set_disappearing_messages(
+    account_id: String,
+    contact: String,
+    seconds: u32,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    if !DISAPPEARING_TIMER_SECS.contains(&seconds) {
+        return Err(WhatsAppError::InvalidDisappearingTimer(seconds));
+    }
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    log_event(&window, "info", format!("Setting disappearing messages to {}s in: {}", seconds, jid));
+    
+    let wa_message = wa::Message {
+        protocol_message: Some(Box::new(wa::message::ProtocolMessage {
+            r#type: Some(wa::message::protocol_message::Type::EphemeralSetting as i32),
+            ephemeral_expiration: Some(seconds),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    
+    let chat = bare_jid(&jid);
+    let result = session.dispatch(|reply| BotCommand::SetDisappearingMessages {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(msg_id) => {
+            log_event(&window, "info", "Disappearing messages updated");
+            let mut timers = session.disappearing_timers.lock().await;
+            if seconds == 0 {
+                timers.remove(&chat);
+            } else {
+                timers.insert(chat, seconds);
+            }
+            Ok(msg_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to set disappearing messages: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Delete a sent message for everyone
+#[tauri::command]
+pub async fn revoke_message(
+    account_id: String,
+    contact: String,
+    message_id: String,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    // Only messages we sent, to this chat, within the revoke window
+    {
+        let sent_messages = session.sent_messages.lock().await;
+        let sent = sent_messages
+            .get(&message_id)
+            .filter(|sent| sent.jid == jid)
+            .ok_or_else(|| WhatsAppError::MessageNotFound(message_id.clone()))?;
+        if now_millis() - sent.sent_at_ms > REVOKE_WINDOW_MS {
+            return Err(WhatsAppError::RevokeExpired(message_id));
+        }
+    }
+    
+    log_event(&window, "info", format!("Revoking message {} in: {}", message_id, jid));
+    
+    let wa_message = wa::Message {
+        protocol_message: Some(Box::new(wa::message::ProtocolMessage {
+            key: Some(wa::MessageKey {
+                remote_jid: Some(jid.to_string()),
+                from_me: Some(true),
+                id: Some(message_id.clone()),
+                ..Default::default()
+            }),
+            r#type: Some(wa::message::protocol_message::Type::Revoke as i32),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    
+    let result = session.dispatch(|reply| BotCommand::RevokeMessage {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(revoke_id) => {
+            log_event(&window, "info", format!("Message {} revoked", message_id));
+            session.sent_messages.lock().await.remove(&message_id);
+            Ok(revoke_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to revoke message: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Replace the text of a sent message
+#[tauri::command]
+pub async fn edit_message(
+    account_id: String,
+    contact: String,
+    message_id: String,
+    new_text: String,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    if new_text.trim().is_empty() {
+        return Err(WhatsAppError::EmptyMessage);
+    }
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    let is_known = session
+        .sent_messages
+        .lock()
+        .await
+        .get(&message_id)
+        .is_some_and(|sent| sent.jid == jid);
+    if !is_known {
+        return Err(WhatsAppError::MessageNotFound(message_id));
+    }
+    
+    log_event(&window, "info", format!("Editing message {} in: {}", message_id, jid));
+    
+    let wa_message = wa::Message {
+        protocol_message: Some(Box::new(wa::message::ProtocolMessage {
+            key: Some(wa::MessageKey {
+                remote_jid: Some(jid.to_string()),
+                from_me: Some(true),
+                id: Some(message_id.clone()),
+                ..Default::default()
+            }),
+            r#type: Some(wa::message::protocol_message::Type::MessageEdit as i32),
+            edited_message: Some(Box::new(wa::Message {
+                conversation: Some(new_text),
+                ..Default::default()
+            })),
+            timestamp_ms: Some(now_millis()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    
+    let result = session.dispatch(|reply| BotCommand::EditMessage {
+        jid,
+        message: wa_message,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(edit_id) => {
+            log_event(&window, "info", format!("Message {} edited", message_id));
+            Ok(edit_id)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to edit message: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Show or clear the "typing..." indicator for a chat.
+// Contacts only see it while the account is online; after set_online(false) it's dropped.
+#[tauri::command]
+pub async fn set_typing(
+    account_id: String,
+    contact: String,
+    typing: bool,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    log_event(&window, "info", format!("Setting typing={} for: {}", typing, jid));
+    
+    let result = session.dispatch(|reply| BotCommand::SetPresence {
+        jid,
+        typing,
+        reply,
+    }).await;
+    
+    if let Err(e) = &result {
+        log_event(&window, "error", format!("Failed to set typing state: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Appear online or offline to contacts. Going offline also freezes
+// "last seen" and hides typing indicators until set_online(true) is called again.
+#[tauri::command]
+pub async fn set_online(
+    account_id: String,
+    online: bool,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    log_event(&window, "info", format!("Setting presence to {}", if online { "available" } else { "unavailable" }));
+    
+    let result = session.dispatch(|reply| BotCommand::SetGlobalPresence { online, reply }).await;
+    
+    if let Err(e) = &result {
+        log_event(&window, "error", format!("Failed to set presence: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Start receiving "presence-update" events for a contact
+#[tauri::command]
+pub async fn subscribe_presence(
+    account_id: String,
+    contact: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    update_presence_subscription(&window, &state, &account_id, &contact, true).await
+}
+
+// Tauri Command: Stop presence updates for a contact, e.g. when its chat is closed
+#[tauri::command]
+pub async fn unsubscribe_presence(
+    account_id: String,
+    contact: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    update_presence_subscription(&window, &state, &account_id, &contact, false).await
+}
+
+// Shared path for subscribe_presence and unsubscribe_presence
+async fn update_presence_subscription(
+    window: &Window,
+    state: &WhatsAppState,
+    account_id: &str,
+    contact: &str,
+    subscribe: bool,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(contact, false)?;
+    
+    log_event(window, "info", format!("{} presence for: {}", if subscribe { "Subscribing to" } else { "Unsubscribing from" }, jid));
+    
+    let result = session.dispatch(|reply| BotCommand::SubscribePresence { jid, subscribe, reply }).await;
+    
+    if let Err(e) = &result {
+        log_event(window, "error", format!("Failed to update presence subscription: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Change the name other users see for this account
+#[tauri::command]
+pub async fn set_profile_name(
+    account_id: String,
+    name: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(WhatsAppError::InvalidProfile("the name is empty".to_string()));
+    }
+    if name.chars().count() > MAX_PROFILE_NAME_CHARS {
+        return Err(WhatsAppError::InvalidProfile(format!(
+            "the name is longer than {} characters",
+            MAX_PROFILE_NAME_CHARS
+        )));
+    }
+    
+    log_event(&window, "info", format!("Setting profile name to: {}", name));
+    
+    let result = session.dispatch(|reply| BotCommand::SetProfileName { name, reply }).await;
+    
+    if let Err(e) = &result {
+        log_event(&window, "error", format!("Failed to set profile name: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Change the account's "About" text; an empty string clears it
+#[tauri::command]
+pub async fn set_status_text(
+    account_id: String,
+    status: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    if status.chars().count() > MAX_STATUS_TEXT_CHARS {
+        return Err(WhatsAppError::InvalidProfile(format!(
+            "the status is longer than {} characters",
+            MAX_STATUS_TEXT_CHARS
+        )));
+    }
+    
+    log_event(&window, "info", format!("Setting status text to: {}", status));
+    
+    let result = session.dispatch(|reply| BotCommand::SetStatusText { status, reply }).await;
+    
+    if let Err(e) = &result {
+        log_event(&window, "error", format!("Failed to set status text: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Send read receipts for a batch of messages in a chat
+#[tauri::command]
+pub async fn mark_read(
+    account_id: String,
+    contact: String,
+    message_ids: Vec<String>,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<usize, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    if message_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    
+    log_event(&window, "info", format!("Marking {} message(s) as read in: {}", message_ids.len(), jid));
+    let chat_key = bare_jid(&jid);
+    
+    let result = session.dispatch(|reply| BotCommand::MarkRead {
+        jid,
+        message_ids,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(count) => {
+            log_event(&window, "info", format!("Sent {} read receipt(s)", count));
+            if let Some(chat) = session.chats.lock().await.get_mut(&chat_key) {
+                chat.unread_count = 0;
+            }
+            Ok(count)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to mark messages as read: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Download a received attachment and save it to disk
+#[tauri::command]
+pub async fn download_media(
+    account_id: String,
+    message_id: String,
+    save_path: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let media = session
+        .media_cache
+        .lock()
+        .await
+        .get(&message_id)
+        .cloned()
+        .ok_or_else(|| WhatsAppError::MediaNotFound(message_id.clone()))?;
+    
+    log_event(&window, "info", format!("Downloading media for message: {}", message_id));
+    
+    let download = session.dispatch_within(state.media_timeout, |reply| BotCommand::DownloadMedia { media, reply });
+    let data = match download.await {
+        Ok(data) => data,
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to download media: {}", e));
+            return Err(e);
+        }
+    };
+    
+    std::fs::write(&save_path, &data).map_err(|e| WhatsAppError::FileWrite(e.to_string()))?;
+    log_event(&window, "info", format!("Saved {} bytes to: {}", data.len(), save_path));
+    
+    Ok(save_path)
+}
+
+// Tauri Command: Fetch a group's subject, description, owner and participants
+#[tauri::command]
+pub async fn get_group_info(
+    account_id: String,
+    group_jid: String, // Full group JID, e.g. "120363012345678901@g.us"
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<GroupInfo, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = group_jid_arg(&group_jid)?;
+    
+    log_event(&window, "info", format!("Fetching group info for: {}", jid));
+    
+    let result = session.dispatch(|reply| BotCommand::GetGroupInfo { jid, reply }).await;
+    
+    match result {
+        Ok(info) => {
+            log_event(&window, "info", format!("Group '{}' has {} participant(s)", info.subject, info.participants.len()));
+            Ok(info)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to fetch group info: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Create a group with this account as its admin, returning the new group's JID
+#[tauri::command]
+pub async fn create_group(
+    account_id: String,
+    subject: String,
+    participants: Vec<String>, // Phone numbers; this account is added automatically
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<String, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let subject = subject.trim().to_string();
+    if subject.is_empty() {
+        return Err(WhatsAppError::InvalidGroup("the subject is empty".to_string()));
+    }
+    if subject.chars().count() > MAX_GROUP_SUBJECT_CHARS {
+        return Err(WhatsAppError::InvalidGroup(format!(
+            "the subject is longer than {} characters",
+            MAX_GROUP_SUBJECT_CHARS
+        )));
+    }
+    
+    let mut jids: Vec<Jid> = Vec::new();
+    for participant in &participants {
+        let jid = contact_to_jid(participant, false)?;
+        if !session.is_self(&jid).await && !jids.contains(&jid) {
+            jids.push(jid);
+        }
+    }
+    if jids.is_empty() {
+        return Err(WhatsAppError::InvalidGroup("a group needs at least one other participant".to_string()));
+    }
+    
+    log_event(&window, "info", format!("Creating group '{}' with {} participant(s)", subject, jids.len()));
+    
+    let participant_list: Vec<String> = jids.iter().map(|jid| jid.to_string()).collect();
+    let result = session.dispatch(|reply| BotCommand::CreateGroup {
+        subject: subject.clone(),
+        participants: jids,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(group_jid) => {
+            let group_jid = group_jid.to_string();
+            log_event(&window, "info", format!("Group created: {}", group_jid));
+            session.emit(&window, "group-created", GroupCreatedEvent {
+                jid: group_jid.clone(),
+                subject,
+                participants: participant_list,
+            });
+            Ok(group_jid)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to create group: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Add, remove, promote or demote group members. Requires admin rights;
+// each participant gets its own result because some can fail while others succeed.
+#[tauri::command]
+pub async fn update_group_participants(
+    account_id: String,
+    group_jid: String, // Full group JID, e.g. "120363012345678901@g.us"
+    action: String, // "add", "remove", "promote" or "demote"
+    participants: Vec<String>, // Phone numbers
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Vec<ParticipantResult>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = group_jid_arg(&group_jid)?;
+    let action = ParticipantAction::parse(&action)?;
+    let mut jids: Vec<Jid> = Vec::new();
+    for participant in &participants {
+        let participant = contact_to_jid(participant, false)?;
+        if !jids.contains(&participant) {
+            jids.push(participant);
+        }
+    }
+    if jids.is_empty() {
+        return Err(WhatsAppError::InvalidGroup("no participants given".to_string()));
+    }
+    
+    log_event(&window, "info", format!("Updating {} participant(s) in group {}", jids.len(), jid));
+    
+    let result = session.dispatch(|reply| BotCommand::UpdateGroupParticipants {
+        jid,
+        action,
+        participants: jids,
+        reply,
+    }).await;
+    
+    match result {
+        Ok(results) => {
+            let failed = results.iter().filter(|r| !r.success).count();
+            log_event(&window, "info", format!("Participant update done: {} ok, {} failed", results.len() - failed, failed));
+            Ok(results)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to update group participants: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Rename a group. Requires admin rights.
+#[tauri::command]
+pub async fn set_group_subject(
+    account_id: String,
+    group_jid: String,
+    subject: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = group_jid_arg(&group_jid)?;
+    let subject = subject.trim().to_string();
+    if subject.is_empty() {
+        return Err(WhatsAppError::InvalidGroup("the subject is empty".to_string()));
+    }
+    if subject.chars().count() > MAX_GROUP_SUBJECT_CHARS {
+        return Err(WhatsAppError::InvalidGroup(format!(
+            "the subject is longer than {} characters",
+            MAX_GROUP_SUBJECT_CHARS
+        )));
+    }
+    
+    log_event(&window, "info", format!("Setting subject of group {} to: {}", jid, subject));
+    
+    let group = jid.to_string();
+    let result = session.dispatch(|reply| BotCommand::SetGroupSubject {
+        jid,
+        subject: subject.clone(),
+        reply,
+    }).await;
+    
+    match result {
+        Ok(()) => {
+            session.emit(&window, "group-metadata-updated", GroupMetadataUpdatedEvent {
+                jid: group,
+                field: "subject",
+                value: subject,
+            });
+            Ok(())
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to set group subject: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Change a group's description; an empty string clears it. Requires admin rights.
+#[tauri::command]
+pub async fn set_group_description(
+    account_id: String,
+    group_jid: String,
+    description: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = group_jid_arg(&group_jid)?;
+    let description = description.trim().to_string();
+    if description.chars().count() > MAX_GROUP_DESCRIPTION_CHARS {
+        return Err(WhatsAppError::InvalidGroup(format!(
+            "the description is longer than {} characters",
+            MAX_GROUP_DESCRIPTION_CHARS
+        )));
+    }
+    
+    log_event(&window, "info", format!("Setting description of group {}", jid));
+    
+    let group = jid.to_string();
+    let result = session.dispatch(|reply| BotCommand::SetGroupDescription {
+        jid,
+        description: description.clone(),
+        reply,
+    }).await;
+    
+    match result {
+        Ok(()) => {
+            session.emit(&window, "group-metadata-updated", GroupMetadataUpdatedEvent {
+                jid: group,
+                field: "description",
+                value: description,
+            });
+            Ok(())
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to set group description: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Get the avatar URL for a contact or group, or None if there isn't one we can see
+#[tauri::command]
+pub async fn get_profile_picture(
+    account_id: String,
+    contact: String,
+    is_group: Option<bool>,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<String>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, is_group.unwrap_or(false))?;
+    let key = jid.to_string();
+    
+    if let Some((url, fetched_at)) = session.profile_pictures.lock().await.get(&key) {
+        if now_millis() - fetched_at < PROFILE_PICTURE_CACHE_MS {
+            return Ok(url.clone());
+        }
+    }
+    
+    log_event(&window, "info", format!("Fetching profile picture for: {}", jid));
+    
+    let result = session.dispatch(|reply| BotCommand::GetProfilePicture { jid, reply }).await;
+    
+    match result {
+        Ok(url) => {
+            session.profile_pictures.lock().await.insert(key, (url.clone(), now_millis()));
+            Ok(url)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to fetch profile picture: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Fetch a business contact's profile; None for regular accounts
+#[tauri::command]
+pub async fn get_business_profile(
+    account_id: String,
+    contact: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Option<BusinessProfile>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let jid = contact_to_jid(&contact, false)?;
+    
+    log_event(&window, "info", format!("Fetching business profile for: {}", jid));
+    
+    let result = session.dispatch(|reply| BotCommand::GetBusinessProfile { jid, reply }).await;
+    
+    if let Err(e) = &result {
+        log_event(&window, "error", format!("Failed to fetch business profile: {}", e));
+    }
+    result
+}
+
+// Tauri Command: Ask WhatsApp whether a phone number has an account
+#[tauri::command]
+pub async fn check_number_exists(
+    account_id: String,
+    contact: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<NumberCheck, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    let phone = normalize_phone(&contact)?;
+    
+    // Repeated lookups for a missing number are a quick way to get rate limited
+    let checked_at = session.unregistered_numbers.lock().await.get(&phone).copied();
+    if checked_at.is_some_and(|t| now_millis() - t < NEGATIVE_NUMBER_CACHE_MS) {
+        log_event(&window, "info", format!("{} recently checked, not on WhatsApp", phone));
+        return Ok(NumberCheck { exists: false, jid: None });
+    }
+    
+    log_event(&window, "info", format!("Checking whether {} is on WhatsApp", phone));
+    
+    let result = session.dispatch(|reply| BotCommand::CheckNumber {
+        phone: phone.clone(),
+        reply,
+    }).await;
+    
+    match result {
+        Ok(Some(jid)) => {
+            log_event(&window, "info", format!("{} is on WhatsApp as {}", phone, jid));
+            session.unregistered_numbers.lock().await.remove(&phone);
+            Ok(NumberCheck { exists: true, jid: Some(jid.to_string()) })
+        }
+        Ok(None) => {
+            log_event(&window, "info", format!("{} is not on WhatsApp", phone));
+            session.unregistered_numbers.lock().await.insert(phone, now_millis());
+            Ok(NumberCheck { exists: false, jid: None })
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to check number: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Page through a chat's history, newest first. Pass the id of the
+// oldest message from the previous page as before_id to get the next one.
+#[tauri::command]
+pub async fn get_messages(
+    account_id: String,
+    contact: String,
+    limit: u32,
+    before_id: Option<String>,
+    is_group: Option<bool>,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Vec<StoredMessage>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    let chat_jid = bare_jid(&contact_to_jid(&contact, is_group.unwrap_or(false))?);
+    
+    let history = session.history.lock().await;
+    let messages = history.chats.get(&chat_jid).map(Vec::as_slice).unwrap_or_default();
+    
+    let end = match before_id {
+        Some(id) => messages
+            .iter()
+            .position(|m| m.id == id)
+            .ok_or(WhatsAppError::CursorNotFound(id))?,
+        None => messages.len(),
+    };
+    
+    Ok(messages[..end].iter().rev().take(limit as usize).cloned().collect())
+}
+
+// Tauri Command: List chats with activity this session, most recent first
+#[tauri::command]
+pub async fn get_chats(
+    account_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Vec<ChatSummary>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    let result = session.dispatch(|reply| BotCommand::GetChats { reply }).await;
+    
+    match result {
+        Ok(chats) => {
+            log_event(&window, "info", format!("Listing {} chat(s)", chats.len()));
+            Ok(chats)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to list chats: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: Unread message counts by chat JID, for badge counters. Chats with nothing
+// unread are left out. Counts live in memory only, so they start from zero on app relaunch.
+#[tauri::command]
+pub async fn get_unread_counts(
+    account_id: String,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<HashMap<String, u32>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    let counts = session
+        .chats
+        .lock()
+        .await
+        .iter()
+        .filter(|(_, chat)| chat.unread_count > 0)
+        .map(|(jid, chat)| (jid.clone(), chat.unread_count))
+        .collect();
+    Ok(counts)
+}
+
+// Tauri Command: Re-fetch app state (contact names, chat settings) from WhatsApp, for when
+// the contact list looks empty or stale after pairing. Emits "resync-complete" when done.
+#[tauri::command]
+pub async fn resync(
+    account_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<(), WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    session.ensure_ready().await?;
+
+    log_event(&window, "info", "Resyncing app state");
+    
+    // A full sync downloads every patch, so give it the media timeout rather than the command one
+    let result = session.dispatch_within(state.media_timeout, |reply| BotCommand::Resync { reply }).await;
+    
+    match result {
+        Ok(()) => {
+            log_event(&window, "info", "App state resync complete");
+            session.emit(&window, "resync-complete", ());
+            Ok(())
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to resync app state: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Tauri Command: List contacts known to the local store
+#[tauri::command]
+pub async fn get_contacts(
+    account_id: String,
+    window: Window,
+    state: State<'_, Arc<WhatsAppState>>,
+) -> Result<Vec<ContactInfo>, WhatsAppError> {
+    let session = state.session(&account_id).await?;
+    let result = session.dispatch(|reply| BotCommand::GetContacts { reply }).await;
+    
+    match result {
+        Ok(contacts) => {
+            log_event(&window, "info", format!("Loaded {} contact(s) from store", contacts.len()));
+            Ok(contacts)
+        }
+        Err(e) => {
+            log_event(&window, "error", format!("Failed to load contacts: {}", e));
+            Err(e)
+        }
+    }
+}
+
+// Helper function to wrap a group id, digits with an optional '-' separator, as a group JID
+fn parse_group_id(group_id: &str) -> Result<Jid, WhatsAppError> {
+    if group_id.is_empty() || !group_id.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        return Err(WhatsAppError::InvalidContact(format!("'{}' is not a valid group id", group_id)));
+    }
+    Ok(Jid::new(group_id, "g.us"))
+}
+
+// Helper function to build the destination JID for a contact.
+// Individual chats take a phone number in any common format ("+91 98765-43210").
+// Groups take the group id as shown in the group JID, either bare ("120363012345678901",
+// or the legacy "919876543210-1612345678") or with the "@g.us" suffix. Group ids are
+// checked but not normalized, since the legacy format uses '-' as a separator.
+// Hidden-number identities end in "@lid" and are kept as they are: they aren't phone
+// numbers, so normalizing them would produce a wrong number. They show up as the sender
+// or participant JID for people with number privacy, mostly in group messages and
+// get_group_info, and in chats WhatsApp has moved to LID addressing.
+fn contact_to_jid(contact: &str, is_group: bool) -> Result<Jid, WhatsAppError> {
+    let contact = contact.trim();
+    
+    if let Some(group_id) = contact.strip_suffix("@g.us") {
+        return parse_group_id(group_id);
+    }
+    if let Some(lid) = contact.strip_suffix("@lid") {
+        if lid.is_empty() || !lid.chars().all(|c| c.is_ascii_digit()) {
+            return Err(WhatsAppError::InvalidContact(format!("'{}' is not a valid LID", contact)));
+        }
+        return Ok(Jid::new(lid, "lid"));
+    }
+    if is_group {
+        return parse_group_id(contact);
+    }
+    
+    // A full user JID, e.g. copied from a "message-received" sender. Senders on a linked
+    // device carry a device suffix ("919876543210:12@s.whatsapp.net"), which is dropped:
+    // messages go to the number, not one of its devices.
+    let contact = match contact.strip_suffix("@s.whatsapp.net") {
+        Some(user) => user.split_once(':').map_or(user, |(number, _device)| number),
+        None => contact,
+    };
+    let phone = normalize_phone(contact)?;
+    Ok(Jid::new(&phone, "s.whatsapp.net"))
+}
+
+// Helper function to check and build a text send: everything send_message does before
+// it needs a session, so it can be tested without one
+fn text_send(contact: &str, is_group: bool, message: &str) -> Result<(Jid, wa::Message), WhatsAppError> {
+    // WhatsApp either rejects these or shows a blank bubble
+    if message.trim().is_empty() {
+        return Err(WhatsAppError::EmptyMessage);
+    }
+    let jid = contact_to_jid(contact, is_group)?;
+    Ok((jid, text_message(message)))
+}
+
+// Helper function to check a media send has something to show. An empty caption is fine
+// and a blank one is sent as none, but with no bytes there is nothing to show at all.
+fn media_caption(data: &[u8], caption: &str) -> Result<String, WhatsAppError> {
+    if data.is_empty() {
+        return Err(WhatsAppError::EmptyMedia);
+    }
+    Ok(if caption.trim().is_empty() { String::new() } else { caption.to_string() })
+}
+
+// Helper function to take a fully qualified JID as given, for destinations contact_to_jid
+// doesn't model (newsletters, status@broadcast). Only checks it has both halves.
+fn raw_jid(jid: &str) -> Result<Jid, WhatsAppError> {
+    let jid = jid.trim();
+    match jid.split_once('@') {
+        Some((user, server))
+            if !user.is_empty() && !server.is_empty() && !server.contains('@') && !jid.contains(char::is_whitespace) =>
+        {
+            Ok(Jid::new(user, server))
+        }
+        _ => Err(WhatsAppError::InvalidContact(format!("'{}' is not a JID of the form user@server", jid))),
+    }
+}
+
+// Helper function to turn a formatted phone number into bare E.164 digits
+fn normalize_phone(input: &str) -> Result<String, WhatsAppError> {
+    let digits: String = input
+        .chars()
+        .filter(|c| !matches!(c, '+' | ' ' | '-' | '(' | ')' | '.'))
+        .collect();
+    
+    if digits.is_empty() {
+        return Err(WhatsAppError::InvalidContact("phone number is empty".to_string()));
+    }
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(WhatsAppError::InvalidContact(format!("'{}' is not a phone number", input)));
+    }
+    // E.164 allows at most 15 digits; anything under 7 can't be a real subscriber number
+    if !(7..=15).contains(&digits.len()) {
+        return Err(WhatsAppError::InvalidContact(format!(
+            "'{}' must have between 7 and 15 digits including the country code",
+            input
+        )));
+    }
+    
+    Ok(digits)
+}
+
+// Helper function to tell whether a JID, with or without a device suffix, is the account's
+// own number; `self_jid` is the bare JID recorded when the account connected
+fn is_own_jid(self_jid: Option<&str>, jid: &Jid) -> bool {
+    self_jid == Some(bare_jid(jid).as_str())
+}
+
+// Helper function to render a JID as user@server, dropping any device suffix
+fn bare_jid(jid: &Jid) -> String {
+    format!("{}@{}", jid.user, jid.server)
+}
+
+// Helper function to build the ContextInfo that renders a message as a quoted reply
+fn build_quote_context(
+    quoted_message_id: Option<String>,
+    quoted_sender: Option<String>,
+) -> Option<wa::ContextInfo> {
+    // An empty id is treated the same as no quote at all
+    let stanza_id = quoted_message_id.filter(|id| !id.trim().is_empty())?;
+
+    let participant = quoted_sender
+        .filter(|sender| !sender.trim().is_empty())
+        .map(|sender| {
+            if sender.contains('@') {
+                sender
+            } else {
+                let clean_sender = sender.replace(['+', ' ', '-'], "");
+                Jid::new(&clean_sender, "s.whatsapp.net").to_string()
+            }
+        });
+
+    Some(wa::ContextInfo {
+        stanza_id: Some(stanza_id),
+        participant,
+        // Filled in by quote_context from whatever is still cached; an empty message
+        // here would render as a blank bubble
+        quoted_message: None,
+        ..Default::default()
+    })
+}
+
+// Helper function to turn mentioned phone numbers into user JIDs, skipping invalid ones
+fn mention_jids(window: &Window, mentions: &[String]) -> Vec<String> {
+    mentions
+        .iter()
+        .filter_map(|mention| match normalize_phone(mention) {
+            Ok(phone) => Some(Jid::new(&phone, "s.whatsapp.net").to_string()),
+            Err(e) => {
+                log_event(window, "warn", format!("Skipping mention: {}", e));
+                None
+            }
+        })
+        .collect()
+}
+
+// Helper function to pull the textual body out of an incoming message
+fn extract_message_text(message: &wa::Message) -> Option<String> {
+    message
+        .conversation
+        .clone()
+        .or_else(|| {
+            message
+                .extended_text_message
+                .as_ref()
+                .and_then(|m| m.text.clone())
+        })
+        .filter(|text| !text.is_empty())
+}
+
+// Helper function for the one-line chat list preview: the text, or a placeholder for media
+fn message_preview(message: &wa::Message) -> Option<String> {
+    if let Some(text) = extract_message_text(message) {
+        return Some(text);
+    }
+    let caption = message
+        .image_message
+        .as_ref()
+        .and_then(|m| m.caption.clone())
+        .or_else(|| message.video_message.as_ref().and_then(|m| m.caption.clone()))
+        .filter(|caption| !caption.is_empty());
+    if caption.is_some() {
+        return caption;
+    }
+    
+    let placeholder = if message.image_message.is_some() {
+        "[image]"
+    } else if message.video_message.is_some() {
+        "[video]"
+    } else if message.audio_message.is_some() {
+        "[audio]"
+    } else if message.document_message.is_some() {
+        "[document]"
+    } else if message.sticker_message.is_some() {
+        "[sticker]"
+    } else if message.contact_message.is_some() {
+        "[contact]"
+    } else {
+        return None;
+    };
+    Some(placeholder.to_string())
+}
+
+// Helper function to capture the download reference of an incoming attachment
+fn media_reference(message: &wa::Message) -> Option<CachedMedia> {
+    let cached = |direct_path: &Option<String>,
+                  media_key: &Option<Vec<u8>>,
+                  file_sha256: &Option<Vec<u8>>,
+                  file_enc_sha256: &Option<Vec<u8>>,
+                  file_length: Option<u64>,
+                  media_type: MediaType| {
+        Some(CachedMedia {
+            direct_path: direct_path.clone()?,
+            media_key: media_key.clone()?,
+            file_sha256: file_sha256.clone()?,
+            file_enc_sha256: file_enc_sha256.clone()?,
+            file_length: file_length.unwrap_or(0),
+            media_type,
+        })
+    };
+    
+    if let Some(m) = &message.image_message {
+        cached(&m.direct_path, &m.media_key, &m.file_sha256, &m.file_enc_sha256, m.file_length, MediaType::Image)
+    } else if let Some(m) = &message.video_message {
+        cached(&m.direct_path, &m.media_key, &m.file_sha256, &m.file_enc_sha256, m.file_length, MediaType::Video)
+    } else if let Some(m) = &message.audio_message {
+        cached(&m.direct_path, &m.media_key, &m.file_sha256, &m.file_enc_sha256, m.file_length, MediaType::Audio)
+    } else if let Some(m) = &message.document_message {
+        cached(&m.direct_path, &m.media_key, &m.file_sha256, &m.file_enc_sha256, m.file_length, MediaType::Document)
+    } else {
+        None
+    }
+}
+
+// Helper function to copy a message with its context replaced by the "Forwarded" flag.
+// Only text and media can be forwarded; anything else returns None.
+fn forwarded_copy(message: &wa::Message) -> Option<wa::Message> {
+    let forwarded = |previous: Option<&wa::ContextInfo>| {
+        // WhatsApp shows "Forwarded many times" once the score gets high enough
+        let score = previous.and_then(|c| c.forwarding_score).unwrap_or(0) + 1;
+        Some(Box::new(wa::ContextInfo {
+            is_forwarded: Some(true),
+            forwarding_score: Some(score),
+            ..Default::default()
+        }))
+    };
+    
+    let mut copy = wa::Message::default();
+    if let Some(text) = &message.conversation {
+        copy.extended_text_message = Some(Box::new(wa::message::ExtendedTextMessage {
+            text: Some(text.clone()),
+            context_info: forwarded(None),
+            ..Default::default()
+        }));
+    } else if let Some(m) = &message.extended_text_message {
+        let mut m = m.clone();
+        m.context_info = forwarded(m.context_info.as_deref());
+        copy.extended_text_message = Some(m);
+    } else if let Some(m) = &message.image_message {
+        let mut m = m.clone();
+        m.context_info = forwarded(m.context_info.as_deref());
+        copy.image_message = Some(m);
+    } else if let Some(m) = &message.video_message {
+        let mut m = m.clone();
+        m.context_info = forwarded(m.context_info.as_deref());
+        copy.video_message = Some(m);
+    } else if let Some(m) = &message.audio_message {
+        let mut m = m.clone();
+        m.context_info = forwarded(m.context_info.as_deref());
+        copy.audio_message = Some(m);
+    } else if let Some(m) = &message.document_message {
+        let mut m = m.clone();
+        m.context_info = forwarded(m.context_info.as_deref());
+        copy.document_message = Some(m);
+    } else if let Some(m) = &message.sticker_message {
+        let mut m = m.clone();
+        m.context_info = forwarded(m.context_info.as_deref());
+        copy.sticker_message = Some(m);
+    } else {
+        return None;
+    }
+    Some(copy)
+}
+
+// Helper function to read a button or list choice: (selected id, its text, the message
+// the buttons were on)
+fn button_reply(message: &wa::Message) -> Option<(String, Option<String>, Option<String>)> {
+    if let Some(r) = &message.buttons_response_message {
+        let text = match &r.response {
+            Some(wa::message::buttons_response_message::Response::SelectedDisplayText(text)) => Some(text.clone()),
+            _ => None,
+        };
+        let original = r.context_info.as_ref().and_then(|c| c.stanza_id.clone());
+        return Some((r.selected_button_id.clone()?, text, original));
+    }
+    if let Some(r) = &message.list_response_message {
+        let selected_id = r.single_select_reply.as_ref().and_then(|s| s.selected_row_id.clone())?;
+        let original = r.context_info.as_ref().and_then(|c| c.stanza_id.clone());
+        return Some((selected_id, r.title.clone(), original));
+    }
+    None
+}
+
+// Helper function to describe a received media message without downloading it
+fn media_message_event(message: &wa::Message, from: String, message_id: String) -> Option<MediaMessageEvent> {
+    let (media_type, mime, file_length, caption, has_thumbnail) = if let Some(m) = &message.image_message {
+        ("image", m.mimetype.clone(), m.file_length, m.caption.clone(), m.jpeg_thumbnail.is_some())
+    } else if let Some(m) = &message.video_message {
+        ("video", m.mimetype.clone(), m.file_length, m.caption.clone(), m.jpeg_thumbnail.is_some())
+    } else if let Some(m) = &message.audio_message {
+        ("audio", m.mimetype.clone(), m.file_length, None, false)
+    } else if let Some(m) = &message.document_message {
+        ("document", m.mimetype.clone(), m.file_length, m.caption.clone(), m.jpeg_thumbnail.is_some())
+    } else {
+        return None;
+    };
+    
+    Some(MediaMessageEvent {
+        from,
+        message_id,
+        media_type,
+        mime,
+        file_length: file_length.unwrap_or(0),
+        caption: caption.filter(|caption| !caption.is_empty()),
+        has_thumbnail,
+    })
+}
+
+// Helper function to build one live location packet; sequence 0 starts the share
+fn live_location_message(latitude: f64, longitude: f64, sequence: i64, time_offset: u32, caption: Option<String>) -> wa::Message {
+    wa::Message {
+        live_location_message: Some(Box::new(wa::message::LiveLocationMessage {
+            degrees_latitude: Some(latitude),
+            degrees_longitude: Some(longitude),
+            sequence_number: Some(sequence),
+            time_offset: Some(time_offset),
+            caption: caption.filter(|c| !c.trim().is_empty()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+}
+
+// Helper function to reject coordinates outside the globe (or NaN)
+fn check_coordinates(latitude: f64, longitude: f64) -> Result<(), WhatsAppError> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(WhatsAppError::InvalidLocation(format!("{}, {} is not a valid coordinate", latitude, longitude)));
+    }
+    Ok(())
+}
+
+// Helper function to find where a message keeps its ContextInfo; protocol messages and
+// other kinds without one return None
+fn context_info_slot(message: &mut wa::Message) -> Option<&mut Option<Box<wa::ContextInfo>>> {
+    if let Some(m) = message.extended_text_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.image_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.video_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.audio_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.document_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.sticker_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.contact_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.poll_creation_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.buttons_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    if let Some(m) = message.live_location_message.as_mut() {
+        return Some(&mut m.context_info);
+    }
+    None
+}
+
+// Helper function to get the current time in milliseconds since the Unix epoch
+fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// How a protocol call failed, as far as whatsapp_rust's typed errors tell
+enum Failure {
+    Connection, // Not connected, or the connection dropped mid-call
+    Timeout,
+    Status(u16), // The server's error code, e.g. 429 for rate-overlimit
+    Unrecognised,
+}
+
+// Helper function to find the first typed error anywhere in a failure's cause chain
+fn classify(error: &anyhow::Error) -> Failure {
+    for cause in error.chain() {
+        if let Some(iq) = cause.downcast_ref::<IqError>() {
+            match iq {
+                IqError::NotConnected | IqError::Disconnected(_) => return Failure::Connection,
+                IqError::Timeout => return Failure::Timeout,
+                IqError::ServerError { code, .. } => return Failure::Status(*code),
+                _ => {}
+            }
+        }
+        if let Some(ClientError::NotConnected) = cause.downcast_ref::<ClientError>() {
+            return Failure::Connection;
+        }
+        if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+            match io.kind() {
+                std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe => return Failure::Connection,
+                std::io::ErrorKind::TimedOut => return Failure::Timeout,
+                _ => {}
+            }
+        }
+    }
+    Failure::Unrecognised
 }
 
-pub struct WhatsAppState {
-    command_tx: Arc<Mutex<Option<mpsc::Sender<BotCommand>>>>,
-    is_authenticated: Arc<Mutex<bool>>,
-    is_ready: Arc<Mutex<bool>>,
+// Markers looked for in the text of errors classify doesn't recognise, first match wins.
+// Protocol markers only, never bare numbers, which a JID or message id could contain.
+type ErrorKind = fn(String) -> WhatsAppError;
+
+const SEND_ERROR_MARKERS: &[(&str, ErrorKind)] = &[
+    ("not connected", WhatsAppError::NotConnected),
+    ("disconnected", WhatsAppError::NotConnected),
+    ("socket closed", WhatsAppError::NotConnected),
+    ("connection closed", WhatsAppError::NotConnected),
+    ("rate-overlimit", WhatsAppError::ServerRateLimited),
+    ("rate limit", WhatsAppError::ServerRateLimited),
+    ("too many", WhatsAppError::ServerRateLimited),
+    ("not-acceptable", WhatsAppError::InvalidRecipient),
+    ("item-not-found", WhatsAppError::InvalidRecipient),
+    ("no devices", WhatsAppError::InvalidRecipient),
+    ("invalid jid", WhatsAppError::InvalidRecipient),
+    ("not registered", WhatsAppError::InvalidRecipient),
+];
+
+const UPLOAD_ERROR_MARKERS: &[(&str, ErrorKind)] = &[
+    ("rejected", WhatsAppError::MediaRejected),
+    ("too large", WhatsAppError::MediaRejected),
+    ("unsupported", WhatsAppError::MediaRejected),
+    ("not connected", WhatsAppError::NotConnected),
+    ("disconnected", WhatsAppError::NotConnected),
+];
+
+// Helper function for errors classify doesn't recognise: looks their text up in a
+// marker table once, logging the outcome so the error can be given a typed match
+fn classify_text<R: Runtime, T: Copy>(window: &Window<R>, error: &anyhow::Error, table: &[(&str, T)]) -> Option<T> {
+    let text = error.to_string().to_lowercase();
+    let found = table.iter().find(|(marker, _)| text.contains(*marker));
+    log_event(window, "warn", format!(
+        "Unrecognised error type, classified by its text ({}): {}",
+        found.map_or("no marker", |(marker, _)| *marker), error
+    ));
+    found.map(|(_, category)| *category)
 }
 
-impl WhatsAppState {
-    pub fn new() -> Self {
-        Self {
-            command_tx: Arc::new(Mutex::new(None)),
-            is_authenticated: Arc::new(Mutex::new(false)),
-            is_ready: Arc::new(Mutex::new(false)),
+// Helper function for yes/no text checks, e.g. "is this a missing picture"
+fn mentions_any(window: &Window, error: &anyhow::Error, markers: &[&str]) -> bool {
+    let table: Vec<(&str, ())> = markers.iter().map(|marker| (*marker, ())).collect();
+    classify_text(window, error, &table).is_some()
+}
+
+// Helper functions to sort a failed protocol call into the categories the frontend reacts
+// to differently; anything unrecognised stays SendFailed / UploadFailed
+fn send_error<R: Runtime>(window: &Window<R>, error: impl Into<anyhow::Error>) -> WhatsAppError {
+    let error = error.into();
+    let text = error.to_string();
+    match classify(&error) {
+        Failure::Connection => WhatsAppError::NotConnected(text),
+        Failure::Status(429) => WhatsAppError::ServerRateLimited(text),
+        Failure::Status(404 | 406) => WhatsAppError::InvalidRecipient(text),
+        Failure::Timeout | Failure::Status(_) => WhatsAppError::SendFailed(text),
+        Failure::Unrecognised => {
+            let kind = classify_text(window, &error, SEND_ERROR_MARKERS).unwrap_or(WhatsAppError::SendFailed);
+            kind(text)
         }
     }
 }
 
-// Serializable QR code event for frontend
-#[derive(Clone, Serialize)]
-struct QrCodeEvent {
-    code: String,
+fn upload_error(window: &Window, error: impl Into<anyhow::Error>) -> WhatsAppError {
+    let error = error.into();
+    let text = error.to_string();
+    match classify(&error) {
+        Failure::Connection => WhatsAppError::NotConnected(text),
+        Failure::Status(413 | 415) => WhatsAppError::MediaRejected(text),
+        Failure::Timeout | Failure::Status(_) => WhatsAppError::UploadFailed(text),
+        Failure::Unrecognised => {
+            let kind = classify_text(window, &error, UPLOAD_ERROR_MARKERS).unwrap_or(WhatsAppError::UploadFailed);
+            kind(text)
+        }
+    }
 }
 
-// Tauri Command: Initialize WhatsApp connection
-#[tauri::command]
-pub async fn init_whatsapp(
-    window: Window,
-    state: State<'_, Arc<WhatsAppState>>,
-) -> Result<(), String> {
-    // Get app data directory (outside of src-tauri to avoid rebuild loops)
-    let app_handle = window.app_handle();
-    let app_data_dir = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| e.to_string())?;
-    
-    // Create the directory if it doesn't exist
-    std::fs::create_dir_all(&app_data_dir).map_err(|e| e.to_string())?;
-    
-    // Database path in app data directory
-    let db_path = app_data_dir.join("whatsapp.db");
-    let db_path_str = db_path.to_string_lossy().to_string();
-    
-    println!("Using database path: {}", db_path_str);
+// Helper function to tell network hiccups, worth retrying, from errors a retry can't fix
+fn is_transient_send_error(window: &Window, error: &anyhow::Error) -> bool {
+    match classify(error) {
+        Failure::Connection | Failure::Timeout => true,
+        Failure::Status(code) => code >= 500,
+        Failure::Unrecognised => mentions_any(
+            window,
+            error,
+            &["timeout", "timed out", "connection reset", "disconnected", "not connected", "broken pipe"],
+        ),
+    }
+}
 
-    let backend = SqliteStore::new(&db_path_str)
-        .await
-        .map_err(|e| e.to_string())?;
+// Helper function that finishes once the idle timeout has passed since `last_activity`.
+// The timeout is read when the wait starts, so a change applies from the next command on.
+async fn idle_timer(last_activity: tokio::time::Instant) {
+    match IDLE_TIMEOUT_SECS.load(Ordering::Relaxed) {
+        0 => std::future::pending().await,
+        secs => tokio::time::sleep_until(last_activity + std::time::Duration::from_secs(secs)).await,
+    }
+}
 
-    let (tx, mut rx) = mpsc::channel::<BotCommand>(32);
-    *state.command_tx.lock().await = Some(tx);
+// Helper function for exponential reconnection backoff: 1s, 2s, 4s... capped at 60s
+fn reconnect_delay(attempt: u32) -> std::time::Duration {
+    let secs = 1u64 << attempt.saturating_sub(1).min(6);
+    std::time::Duration::from_secs(secs.min(60))
+}
 
-    let window_clone = window.clone();
-    let state_clone = state.inner().clone();
+// Helper function to log to the console and, with debug logging on, to the frontend
+fn log_event<R: Runtime>(window: &Window<R>, level: &str, message: impl Into<String>) {
+    let message = message.into();
+    log_console(level, &message);
     
-    tokio::spawn(async move {
-        let state_for_events = state_clone.clone();
-        let window_for_logout = window_clone.clone();
-        
-        let bot_result = Bot::builder()
-            .with_backend(Arc::new(backend))
-            .with_transport_factory(TokioWebSocketTransportFactory::new())
-            .with_http_client(UreqHttpClient::new())
-            .on_event(move |event, _client| {
-                let window = window_clone.clone();
-                let state = state_for_events.clone();
-                
-                async move {
-                    match event {
-                        Event::PairingQrCode { code, .. } => {
-                            println!("QR Code generated");
-                            let _ = window.emit("qr-code", QrCodeEvent { code });
-                        }
-                        
-                        Event::PairSuccess(_) => {
-                            println!("Pair success event received");
-                            *state.is_authenticated.lock().await = true;
-                            let _ = window.emit("auth-success", ());
-                        }
-                        
-                        Event::Connected(_) => {
-                            println!("Connected event received - Bot is fully ready");
-                            *state.is_authenticated.lock().await = true;
-                            *state.is_ready.lock().await = true;
-                            let _ = window.emit("auth-success", ());
-                        }
-                        
-                        Event::LoggedOut(_) => {
-                            println!("Logged out event received");
-                            *state.is_authenticated.lock().await = false;
-                            *state.is_ready.lock().await = false;
-                            let _ = window.emit("logged-out", ());
-                        }
-                        
-                        Event::Message(_msg, info) => {
-                            println!("Message received from: {:?}", info.source.sender);
-                        }
-                        
-                        _ => {}
-                    }
-                }
-            })
-            .build()
-            .await;
+    if DEBUG_LOGGING.load(Ordering::Relaxed) {
+        let _ = window.emit("debug-log", DebugLogEvent {
+            level: level.to_string(),
+            message,
+        });
+    }
+}
 
-        match bot_result {
-            Ok(mut bot) => {
-                println!("Bot built successfully, starting...");
-                match bot.run().await {
-                    Ok(handle) => {
-                        println!("Bot started successfully");
-                        let client = bot.client();
-                        
-                        // Process commands via channel on the SAME task as the bot.
-                        // This avoids cross-thread Rc access that causes crashes.
-                        tokio::pin!(handle);
-                        loop {
-                            tokio::select! {
-                                cmd = rx.recv() => {
-                                    match cmd {
-                                        Some(BotCommand::SendMessage { jid, message, reply }) => {
-                                            println!("Processing SendMessage command");
-                                            let result = client.send_message(jid, message).await
-                                                .map_err(|e| format!("Failed to send: {}", e));
-                                            let _ = reply.send(result);
-                                        }
-                                        Some(BotCommand::SendMediaMessage {
-                                            jid, media_data, media_type_enum,
-                                            media_category, mime_type, caption,
-                                            file_name, reply
-                                        }) => {
-                                            println!("Processing SendMediaMessage command");
-                                            let result = async {
-                                                println!("Uploading media...");
-                                                let uploaded = client.upload(media_data, media_type_enum)
-                                                    .await.map_err(|e| {
-                                                        eprintln!("Upload failed: {}", e);
-                                                        e.to_string()
-                                                    })?;
-                                                println!("Media uploaded successfully");
-                                                
-                                                let wa_message = match media_category.as_str() {
-                                                    "image" => {
-                                                        let mut img_msg = wa::message::ImageMessage {
-                                                            url: Some(uploaded.url),
-                                                            direct_path: Some(uploaded.direct_path),
-                                                            media_key: Some(uploaded.media_key.to_vec()),
-                                                            file_enc_sha256: Some(uploaded.file_enc_sha256.to_vec()),
-                                                            file_sha256: Some(uploaded.file_sha256.to_vec()),
-                                                            file_length: Some(uploaded.file_length),
-                                                            mimetype: Some(mime_type),
-                                                            ..Default::default()
-                                                        };
-                                                        if !caption.is_empty() {
-                                                            img_msg.caption = Some(caption);
-                                                        }
-                                                        wa::Message {
-                                                            image_message: Some(Box::new(img_msg)),
-                                                            ..Default::default()
-                                                        }
-                                                    },
-                                                    "video" => {
-                                                        let mut vid_msg = wa::message::VideoMessage {
-                                                            url: Some(uploaded.url),
-                                                            direct_path: Some(uploaded.direct_path),
-                                                            media_key: Some(uploaded.media_key.to_vec()),
-                                                            file_enc_sha256: Some(uploaded.file_enc_sha256.to_vec()),
-                                                            file_sha256: Some(uploaded.file_sha256.to_vec()),
-                                                            file_length: Some(uploaded.file_length),
-                                                            mimetype: Some(mime_type),
-                                                            ..Default::default()
-                                                        };
-                                                        if !caption.is_empty() {
-                                                            vid_msg.caption = Some(caption);
-                                                        }
-                                                        wa::Message {
-                                                            video_message: Some(Box::new(vid_msg)),
-                                                            ..Default::default()
-                                                        }
-                                                    },
-                                                    _ => {
-                                                        let doc_msg = wa::message::DocumentMessage {
-                                                            url: Some(uploaded.url),
-                                                            direct_path: Some(uploaded.direct_path),
-                                                            media_key: Some(uploaded.media_key.to_vec()),
-                                                            file_enc_sha256: Some(uploaded.file_enc_sha256.to_vec()),
-                                                            file_sha256: Some(uploaded.file_sha256.to_vec()),
-                                                            file_length: Some(uploaded.file_length),
-                                                            mimetype: Some(mime_type),
-                                                            file_name: Some(file_name),
-                                                            ..Default::default()
-                                                        };
-                                                        wa::Message {
-                                                            document_message: Some(Box::new(doc_msg)),
-                                                            ..Default::default()
-                                                        }
-                                                    },
-                                                };
-                                                
-                                                client.send_message(jid, wa_message).await
-                                                    .map_err(|e| format!("Failed to send media: {}", e))
-                                            }.await;
-                                            let _ = reply.send(result);
-                                        }
-                                        None => {
-                                            println!("Command channel closed");
-                                            break;
-                                        }
-                                    }
-                                }
-                                _ = &mut handle => {
-                                    println!("Bot handle completed");
-                                    break;
-                                }
-                            }
-                        }
-                        
-                        // Bot stopped - reset state
-                        println!("Bot task ending, resetting state");
-                        *state_clone.is_ready.lock().await = false;
-                        *state_clone.is_authenticated.lock().await = false;
-                        let _ = window_for_logout.emit("logged-out", ());
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to run bot: {}", e);
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Failed to build bot: {}", e);
-            }
-        }
-    });
+// Helper function to log to the console only, for the few places with no window to hand
+fn log_console(level: &str, message: &str) {
+    if matches!(level, "error" | "warn") {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
 
-    Ok(())
+// Helper function to parse a command's group JID argument, which must be a full "@g.us" JID
+fn group_jid_arg(group_jid: &str) -> Result<Jid, WhatsAppError> {
+    let group_jid = group_jid.trim();
+    if !group_jid.ends_with("@g.us") {
+        return Err(WhatsAppError::InvalidContact(format!("'{}' is not a group JID", group_jid)));
+    }
+    contact_to_jid(group_jid, true)
 }
 
-// Tauri Command: Check if bot is ready
-#[tauri::command]
-pub async fn is_bot_ready(
-    state: State<'_, Arc<WhatsAppState>>,
-) -> Result<bool, String> {
-    let is_ready = *state.is_ready.lock().await;
-    Ok(is_ready)
+// Helper function to classify a failed group query. The server answers non-members with a 403.
+fn group_query_error(window: &Window, jid: &Jid, error: impl Into<anyhow::Error>) -> WhatsAppError {
+    let error = error.into();
+    let not_member = match classify(&error) {
+        Failure::Connection => return WhatsAppError::NotConnected(error.to_string()),
+        Failure::Status(code) => matches!(code, 401 | 403),
+        Failure::Timeout => false,
+        Failure::Unrecognised => mentions_any(window, &error, &["forbidden", "not-authorized"]),
+    };
+    if not_member {
+        WhatsAppError::NotGroupMember(jid.to_string())
+    } else {
+        WhatsAppError::QueryFailed(error.to_string())
+    }
 }
 
-// Tauri Command: Send text message
-#[tauri::command]
-pub async fn send_message(
-    contact: String,
-    message: String,
-    state: State<'_, Arc<WhatsAppState>>,
-) -> Result<String, String> {
-    let is_ready = *state.is_ready.lock().await;
-    if !is_ready {
-        return Err("WhatsApp is not ready yet. Please wait for connection to complete.".to_string());
+// Helper function to check this account may change a group before trying, since the
+// server's refusal doesn't say why
+async fn ensure_group_admin(
+    window: &Window,
+    client: &Arc<Client>,
+    session: &AccountSession,
+    jid: &Jid,
+) -> Result<(), WhatsAppError> {
+    let metadata = client.groups().get_metadata(jid).await
+        .map_err(|e| group_query_error(window, jid, e))?;
+    for participant in &metadata.participants {
+        if session.is_self(&participant.jid).await {
+            return if participant.is_admin || participant.is_super_admin {
+                Ok(())
+            } else {
+                Err(WhatsAppError::NotGroupAdmin(jid.to_string()))
+            };
+        }
     }
+    Err(WhatsAppError::NotGroupMember(jid.to_string()))
+}
 
-    let clean_contact = contact.replace(['+', ' ', '-'], "");
-    println!("Sending message to contact: {}", clean_contact);
-    
-    let jid = Jid::new(&clean_contact, "s.whatsapp.net");
-    println!("Parsed JID: {}", jid);
-    
-    let wa_message = wa::Message {
+// Helper function to explain the per-participant status codes from a group update
+fn participant_error(status: &str) -> String {
+    match status {
+        "403" => "their privacy settings don't allow being added to groups".to_string(),
+        "404" => "not a WhatsApp user".to_string(),
+        "408" => "they recently left this group".to_string(),
+        "409" => "already in the group".to_string(),
+        _ => format!("failed with status {}", status),
+    }
+}
+
+// Helper function to build the message for an uploaded file; view once wrapping is left to the caller
+#[allow(clippy::too_many_arguments)]
+fn media_message(
+    media_category: &str,
+    upload: UploadedMedia,
+    mime_type: String,
+    caption: String,
+    file_name: String,
+    ptt: bool,
+    view_once: bool,
+    gif_playback: bool,
+    context: Option<wa::ContextInfo>,
+    jpeg_thumbnail: Option<Vec<u8>>,
+) -> wa::Message {
+    match media_category {
+        "image" => {
+            let mut img_msg = wa::message::ImageMessage {
+                url: Some(upload.url),
+                direct_path: Some(upload.direct_path),
+                media_key: Some(upload.media_key),
+                file_enc_sha256: Some(upload.file_enc_sha256),
+                file_sha256: Some(upload.file_sha256),
+                file_length: Some(upload.file_length),
+                mimetype: Some(mime_type),
+                context_info: context.map(Box::new),
+                jpeg_thumbnail,
+                ..Default::default()
+            };
+            if !caption.is_empty() {
+                img_msg.caption = Some(caption);
+            }
+            if view_once {
+                img_msg.view_once = Some(true);
+            }
+            wa::Message {
+                image_message: Some(Box::new(img_msg)),
+                ..Default::default()
+            }
+        },
+        "video" => {
+            let mut vid_msg = wa::message::VideoMessage {
+                url: Some(upload.url),
+                direct_path: Some(upload.direct_path),
+                media_key: Some(upload.media_key),
+                file_enc_sha256: Some(upload.file_enc_sha256),
+                file_sha256: Some(upload.file_sha256),
+                file_length: Some(upload.file_length),
+                mimetype: Some(mime_type),
+                context_info: context.map(Box::new),
+                jpeg_thumbnail,
+                ..Default::default()
+            };
+            if !caption.is_empty() {
+                vid_msg.caption = Some(caption);
+            }
+            if view_once {
+                vid_msg.view_once = Some(true);
+            }
+            if gif_playback {
+                vid_msg.gif_playback = Some(true);
+            }
+            wa::Message {
+                video_message: Some(Box::new(vid_msg)),
+                ..Default::default()
+            }
+        },
+        "audio" => {
+            // Audio has no caption field, so the caption is dropped
+            let audio_msg = wa::message::AudioMessage {
+                url: Some(upload.url),
+                direct_path: Some(upload.direct_path),
+                media_key: Some(upload.media_key),
+                file_enc_sha256: Some(upload.file_enc_sha256),
+                file_sha256: Some(upload.file_sha256),
+                file_length: Some(upload.file_length),
+                mimetype: Some(mime_type),
+                ptt: Some(ptt),
+                context_info: context.map(Box::new),
+                ..Default::default()
+            };
+            wa::Message {
+                audio_message: Some(Box::new(audio_msg)),
+                ..Default::default()
+            }
+        },
+        _ => {
+            let mut doc_msg = wa::message::DocumentMessage {
+                url: Some(upload.url),
+                direct_path: Some(upload.direct_path),
+                media_key: Some(upload.media_key),
+                file_enc_sha256: Some(upload.file_enc_sha256),
+                file_sha256: Some(upload.file_sha256),
+                file_length: Some(upload.file_length),
+                mimetype: Some(mime_type),
+                title: Some(file_name.clone()),
+                file_name: Some(file_name),
+                context_info: context.map(Box::new),
+                jpeg_thumbnail,
+                ..Default::default()
+            };
+            if !caption.is_empty() {
+                doc_msg.caption = Some(caption);
+            }
+            wa::Message {
+                document_message: Some(Box::new(doc_msg)),
+                ..Default::default()
+            }
+        },
+    }
+}
+
+// Helper function to wrap plain text in the message type that supports quotes and mentions
+fn text_message(text: &str) -> wa::Message {
+    wa::Message {
         extended_text_message: Some(Box::new(wa::message::ExtendedTextMessage {
-            text: Some(message.clone()),
+            text: Some(text.to_string()),
             ..Default::default()
         })),
         ..Default::default()
-    };
+    }
+}
 
-    println!("Attempting to send message: {}", message);
-    
-    // Send command to bot task via channel (avoids cross-thread Rc crash)
-    let (reply_tx, reply_rx) = oneshot::channel();
+// Helper function to build a minimal vCard; waid lets WhatsApp offer "Message" on the card
+fn build_vcard(name: &str, phone: &str) -> String {
+    format!(
+        "BEGIN:VCARD\nVERSION:3.0\nFN:{}\nTEL;type=CELL;waid={}:+{}\nEND:VCARD",
+        name, phone, phone
+    )
+}
+
+// Helper function to downscale an image into a small JPEG preview; None if it can't be decoded
+fn generate_thumbnail(window: &Window, data: &[u8]) -> Option<Vec<u8>> {
+    let image = match image::load_from_memory(data) {
+        Ok(image) => image,
+        Err(e) => {
+            log_event(window, "warn", format!("Skipping thumbnail: {}", e));
+            return None;
+        }
+    };
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
     
-    let tx = {
-        let guard = state.command_tx.lock().await;
-        guard.as_ref().ok_or("WhatsApp not initialized")?.clone()
+    let mut jpeg = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 70);
+    if let Err(e) = encoder.encode_image(&thumbnail) {
+        log_event(window, "warn", format!("Skipping thumbnail: {}", e));
+        return None;
+    }
+    Some(jpeg)
+}
+
+// Turns a path from the frontend into a local file path and returns it with the file's
+// size. Some pickers hand over file:// URLs, which std::fs would report as missing.
+fn check_input_file(raw: &str) -> Result<(std::path::PathBuf, u64), WhatsAppError> {
+    let path = match raw.strip_prefix("file://") {
+        Some(rest) => {
+            let decoded = percent_decode(rest);
+            // file:///C:/Users/... keeps a slash in front of the drive letter
+            if cfg!(windows) && decoded.len() > 2 && decoded.starts_with('/') && decoded.as_bytes()[2] == b':' {
+                decoded[1..].to_string()
+            } else {
+                decoded
+            }
+        }
+        None => raw.to_string(),
     };
+    let path = std::path::PathBuf::from(path);
     
-    tx.send(BotCommand::SendMessage {
-        jid,
-        message: wa_message,
-        reply: reply_tx,
-    }).await.map_err(|_| "Failed to send command to bot task".to_string())?;
+    let metadata = std::fs::metadata(&path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => WhatsAppError::FileNotFound { path: path.display().to_string() },
+        _ => WhatsAppError::FileNotReadable { path: path.display().to_string(), reason: e.to_string() },
+    })?;
+    if metadata.is_dir() {
+        return Err(WhatsAppError::FileNotReadable {
+            path: path.display().to_string(),
+            reason: "it is a directory".to_string(),
+        });
+    }
     
-    match reply_rx.await {
-        Ok(Ok(msg_id)) => {
-            println!("Message sent successfully with ID: {}", msg_id);
-            Ok(msg_id)
-        }
-        Ok(Err(e)) => {
-            eprintln!("Failed to send message: {}", e);
-            Err(e)
+    let size = metadata.len();
+    Ok((path, size))
+}
+
+// Reads a file already checked by check_input_file
+fn read_input_file(path: &std::path::Path) -> Result<Vec<u8>, WhatsAppError> {
+    std::fs::read(path).map_err(|e| WhatsAppError::FileNotReadable {
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    })
+}
+
+// Helper function to make a file name safe to show: control characters and path separators
+// are dropped while emoji and other unicode (e.g. "📄 报告.pdf") are kept. Falls back to
+// "document" if nothing is left.
+fn sanitize_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '/' | '\\'))
+        .collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() {
+        "document".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+// Helper function to %XX-escape proxy credentials so ':' or '@' in them can't break the URL
+fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
         }
-        Err(_) => Err("Bot task dropped before responding".to_string()),
     }
+    encoded
 }
 
-// Tauri Command: Send message with media
-#[tauri::command]
-pub async fn send_media_message(
-    contact: String,
-    message_text: String,
-    media_path: String,
-    media_type: String, // "image", "video", "document"
-    state: State<'_, Arc<WhatsAppState>>,
-) -> Result<String, String> {
-    let is_ready = *state.is_ready.lock().await;
-    if !is_ready {
-        return Err("WhatsApp is not ready yet. Please wait for connection to complete.".to_string());
+// Helper function to decode %XX escapes in a file:// URL (e.g. %20 for a space)
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
     }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
 
-    let clean_contact = contact.replace(['+', ' ', '-'], "");
-    let jid = Jid::new(&clean_contact, "s.whatsapp.net");
-    
-    println!("Sending {} to: {}", media_type, clean_contact);
-    
-    let media_data = std::fs::read(&media_path).map_err(|e| e.to_string())?;
-    println!("Read media file: {} bytes", media_data.len());
-    
-    let (media_type_enum, mime_type) = get_media_type_and_mime(&media_type, &media_path);
-    
-    let file_name = std::path::Path::new(&media_path)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("document")
-        .to_string();
-    
-    // Send command to bot task via channel (avoids cross-thread Rc crash)
-    let (reply_tx, reply_rx) = oneshot::channel();
-    
-    let tx = {
-        let guard = state.command_tx.lock().await;
-        guard.as_ref().ok_or("WhatsApp not initialized")?.clone()
+// Helper function to check for a RIFF container holding WebP data
+fn is_webp(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP"
+}
+
+// Helper function to detect animated WebP: an extended (VP8X) header with the animation flag set
+fn is_animated_webp(data: &[u8]) -> bool {
+    data.len() >= 21 && &data[12..16] == b"VP8X" && data[20] & 0x02 != 0
+}
+
+// Helper function to check a caller-supplied MIME type has a known top-level type and a
+// token-shaped subtype; returns it lowercased, with any parameters (";codecs=...") kept
+fn validate_mime(mime: &str) -> Result<String, WhatsAppError> {
+    let mime = mime.trim().to_lowercase();
+    let essence = mime.split(';').next().unwrap_or("").trim();
+    let is_token = |part: &str| {
+        !part.is_empty()
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&^_.+-".contains(c))
     };
-    
-    tx.send(BotCommand::SendMediaMessage {
-        jid,
-        media_data,
-        media_type_enum,
-        media_category: media_type,
-        mime_type,
-        caption: message_text,
-        file_name,
-        reply: reply_tx,
-    }).await.map_err(|_| "Failed to send command to bot task".to_string())?;
-    
-    match reply_rx.await {
-        Ok(Ok(msg_id)) => {
-            println!("Media message sent successfully with ID: {}", msg_id);
-            Ok(msg_id)
-        }
-        Ok(Err(e)) => {
-            eprintln!("Failed to send media message: {}", e);
-            Err(e)
+    let plausible = match essence.split_once('/') {
+        Some((top, sub)) => {
+            matches!(top, "application" | "audio" | "font" | "image" | "model" | "text" | "video")
+                && is_token(sub)
         }
-        Err(_) => Err("Bot task dropped before responding".to_string()),
+        None => false,
+    };
+    if !plausible {
+        return Err(WhatsAppError::InvalidMime(mime));
     }
+    Ok(mime)
 }
 
 // Helper function to determine MediaType and MIME type
@@ -426,7 +6547,8 @@ fn get_media_type_and_mime(type_str: &str, file_path: &str) -> (MediaType, Strin
         "audio" => {
             let mime = match extension.as_str() {
                 "mp3" => "audio/mpeg",
-                "ogg" => "audio/ogg",
+                // Voice notes must be opus in an ogg container
+                "ogg" | "opus" => "audio/ogg; codecs=opus",
                 "wav" => "audio/wav",
                 "m4a" => "audio/mp4",
                 _ => "audio/mpeg",
@@ -447,4 +6569,244 @@ fn get_media_type_and_mime(type_str: &str, file_path: &str) -> (MediaType, Strin
             (MediaType::Document, mime.to_string())
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+    // Stands in for the bot task: records each text send and answers it with a fixed id
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: std::sync::Mutex<Vec<(Jid, wa::Message)>>,
+    }
+
+    impl CommandSink for RecordingSink {
+        fn submit(&self, command: BotCommand) -> Result<(), WhatsAppError> {
+            let BotCommand::SendMessage { jid, message, reply, .. } = command else {
+                panic!("only text sends are expected");
+            };
+            self.sent.lock().unwrap().push((jid, message));
+            let _ = reply.send(Ok(Delivery::Sent("3EB0MOCK".to_string())));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn send_message_routes_extended_text_to_normalized_jid() {
+        let sink = RecordingSink::default();
+        let (jid, message) = text_send("+91 98765-43210", false, "hello").unwrap();
+        let delivery = route_text(&sink, TEST_TIMEOUT, jid, message, None, 0).await.unwrap();
+        assert_eq!(delivery, Delivery::Sent("3EB0MOCK".to_string()));
+
+        let sent = sink.sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        let (jid, message) = &sent[0];
+        assert_eq!(jid.to_string(), "919876543210@s.whatsapp.net");
+        let text = message.extended_text_message.as_ref().expect("an ExtendedTextMessage");
+        assert_eq!(text.text.as_deref(), Some("hello"));
+    }
+
+    #[tokio::test]
+    async fn blank_text_is_rejected_before_dispatch() {
+        let sink = RecordingSink::default();
+        for text in ["", "   ", "\n\t"] {
+            let result = match text_send("919876543210", false, text) {
+                Ok((jid, message)) => route_text(&sink, TEST_TIMEOUT, jid, message, None, 0).await,
+                Err(e) => Err(e),
+            };
+            assert!(matches!(result, Err(WhatsAppError::EmptyMessage)), "{:?} was accepted", text);
+        }
+        assert!(sink.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn blank_captions_are_dropped_but_empty_media_is_rejected() {
+        for caption in ["", "   ", "\n\t"] {
+            assert_eq!(media_caption(b"\xFF\xD8", caption).unwrap(), "");
+            assert!(matches!(media_caption(b"", caption), Err(WhatsAppError::EmptyMedia)));
+        }
+        assert_eq!(media_caption(b"\xFF\xD8", "Holiday").unwrap(), "Holiday");
+    }
+
+    #[tokio::test]
+    async fn full_command_channel_is_busy() {
+        let (tx, _rx) = mpsc::channel(1);
+        tx.try_send(BotCommand::Wake).unwrap();
+        let (jid, message) = text_send("919876543210", false, "hello").unwrap();
+        let result = route_text(&tx, TEST_TIMEOUT, jid, message, None, 0).await;
+        assert!(matches!(result, Err(WhatsAppError::BusyTryAgain)));
+    }
+
+    #[test]
+    fn every_upload_uses_the_media_lane() {
+        let jid = Jid::new("919876543210", "s.whatsapp.net");
+        let sticker = BotCommand::SendSticker {
+            jid: jid.clone(),
+            media_data: vec![0; 16],
+            is_animated: false,
+            reply: oneshot::channel().0,
+        };
+        let media_status = BotCommand::PostStatus {
+            content: StatusContent::Media {
+                data: vec![0; 16],
+                media_type_enum: MediaType::Image,
+                media_category: "image".to_string(),
+                mime_type: "image/jpeg".to_string(),
+                caption: String::new(),
+                jpeg_thumbnail: None,
+            },
+            reply: oneshot::channel().0,
+        };
+        let text_status = BotCommand::PostStatus {
+            content: StatusContent::Text { text: "hi".to_string(), background_argb: 0xFF00_0000 },
+            reply: oneshot::channel().0,
+        };
+        let text = BotCommand::SendMessage {
+            jid,
+            message: text_message("hi"),
+            context: None,
+            retries: 0,
+            reply: oneshot::channel().0,
+        };
+        assert!(sticker.uploads_media());
+        assert!(media_status.uploads_media());
+        assert!(!text_status.uploads_media());
+        assert!(!text.uploads_media());
+    }
+
+    // The bot task's command loop with a mock client: uploads take UPLOAD, texts are instant
+    #[tokio::test]
+    async fn text_sends_are_not_held_up_by_a_slow_upload() {
+        const UPLOAD: std::time::Duration = std::time::Duration::from_millis(500);
+        let (tx, mut rx) = mpsc::channel::<BotCommand>(COMMAND_CHANNEL_CAPACITY);
+        let bot = tokio::spawn(async move {
+            let mut media_lane = MediaLane::default();
+            loop {
+                media_lane.start_next(|command| Box::pin(async move {
+                    if let BotCommand::SendSticker { reply, .. } = command {
+                        tokio::time::sleep(UPLOAD).await;
+                        let _ = reply.send(Ok("STICKER".to_string()));
+                    }
+                }));
+                tokio::select! {
+                    _ = media_lane.finished() => {}
+                    command = rx.recv() => match command.and_then(|command| media_lane.offer(command)) {
+                        Some(BotCommand::SendMessage { reply, .. }) => {
+                            let _ = reply.send(Ok(Delivery::Sent("TEXT".to_string())));
+                        }
+                        Some(_) => panic!("unexpected command"),
+                        None => {}
+                    },
+                }
+            }
+        });
+
+        let jid = Jid::new("919876543210", "s.whatsapp.net");
+        let (sticker_reply, mut sticker_rx) = oneshot::channel();
+        tx.submit(BotCommand::SendSticker {
+            jid: jid.clone(),
+            media_data: vec![0; 16],
+            is_animated: false,
+            reply: sticker_reply,
+        }).unwrap();
+
+        let started = tokio::time::Instant::now();
+        let text = route_text(&tx, UPLOAD * 4, jid, text_message("hi"), None, 0).await;
+        let text_latency = started.elapsed();
+        assert_eq!(text.unwrap(), Delivery::Sent("TEXT".to_string()));
+        assert!(text_latency < UPLOAD / 5, "text waited {:?} behind the upload", text_latency);
+        assert!(sticker_rx.try_recv().is_err(), "upload finished before the text was answered");
+
+        let sticker = tokio::time::timeout(UPLOAD * 4, sticker_rx).await.unwrap().unwrap();
+        assert_eq!(sticker.unwrap(), "STICKER");
+        bot.abort();
+    }
+
+    #[test]
+    fn normalize_phone_strips_formatting() {
+        assert_eq!(normalize_phone("+91 98765-43210").unwrap(), "919876543210");
+    }
+
+    #[test]
+    fn normalize_phone_rejects_non_numbers() {
+        assert!(matches!(normalize_phone("abc"), Err(WhatsAppError::InvalidContact(_))));
+        assert!(matches!(normalize_phone(""), Err(WhatsAppError::InvalidContact(_))));
+    }
+
+    #[test]
+    fn is_own_jid_matches_the_bare_and_device_forms() {
+        let own = Some("919876543210@s.whatsapp.net");
+        assert!(is_own_jid(own, &Jid::new("919876543210", "s.whatsapp.net")));
+        let device: Jid = "919876543210:12@s.whatsapp.net".parse().unwrap();
+        assert!(is_own_jid(own, &device));
+        assert!(!is_own_jid(own, &Jid::new("919812345678", "s.whatsapp.net")));
+        assert!(!is_own_jid(None, &Jid::new("919876543210", "s.whatsapp.net")));
+    }
+
+    #[test]
+    fn device_suffixed_contacts_go_to_the_number() {
+        let jid = contact_to_jid("919876543210:12@s.whatsapp.net", false).unwrap();
+        assert_eq!(jid.to_string(), "919876543210@s.whatsapp.net");
+    }
+
+    #[test]
+    fn group_contacts_must_be_group_ids() {
+        let jid = contact_to_jid("120363012345678901@g.us", false).unwrap();
+        assert_eq!(jid.to_string(), "120363012345678901@g.us");
+        let jid = contact_to_jid("919876543210-1612345678", true).unwrap();
+        assert_eq!(jid.to_string(), "919876543210-1612345678@g.us");
+        assert!(matches!(contact_to_jid("@g.us", false), Err(WhatsAppError::InvalidContact(_))));
+        assert!(matches!(contact_to_jid("+91 98765", true), Err(WhatsAppError::InvalidContact(_))));
+    }
+
+    #[test]
+    fn quote_context_starts_without_a_quoted_message() {
+        let context = build_quote_context(Some("ABC123".to_string()), Some("+91 98765 43210".to_string())).unwrap();
+        assert_eq!(context.stanza_id.as_deref(), Some("ABC123"));
+        assert_eq!(context.participant.as_deref(), Some("919876543210@s.whatsapp.net"));
+        assert!(context.quoted_message.is_none());
+        assert!(build_quote_context(Some("  ".to_string()), None).is_none());
+    }
+
+    #[test]
+    fn sanitize_file_name_keeps_unicode() {
+        assert_eq!(sanitize_file_name("📄 报告.pdf"), "📄 报告.pdf");
+    }
+
+    #[test]
+    fn sanitize_file_name_strips_separators_and_control_characters() {
+        assert_eq!(sanitize_file_name("../reports\\q1\n.pdf\u{7}"), "..reportsq1.pdf");
+        assert_eq!(sanitize_file_name("/\t/"), "document");
+    }
+
+    #[test]
+    fn bounded_map_evicts_oldest_first() {
+        let mut map = BoundedMap::new(2);
+        assert!(map.insert("a".to_string(), 1));
+        assert!(map.insert("b".to_string(), 2));
+        // Replacing a value doesn't make the key any younger
+        assert!(!map.insert("a".to_string(), 3));
+        assert!(map.insert("c".to_string(), 4));
+        assert_eq!(map.get("a"), None);
+        assert_eq!(map.get("b"), Some(&2));
+        assert_eq!(map.get("c"), Some(&4));
+    }
+
+    #[test]
+    fn untyped_send_errors_are_sorted_by_their_text() {
+        let app = tauri::test::mock_app();
+        let webview = tauri::WebviewWindowBuilder::new(&app, "main", tauri::WebviewUrl::default())
+            .build()
+            .unwrap();
+        let window = webview.as_ref().window();
+
+        let error = send_error(&window, anyhow::anyhow!("server said rate-overlimit"));
+        assert!(matches!(error, WhatsAppError::ServerRateLimited(_)));
+        let error = send_error(&window, anyhow::anyhow!("Socket closed by peer"));
+        assert!(matches!(error, WhatsAppError::NotConnected(_)));
+        let error = send_error(&window, anyhow::anyhow!("something else entirely"));
+        assert!(matches!(error, WhatsAppError::SendFailed(_)));
+    }
+}
@@ -5,6 +5,13 @@ import { open } from '@tauri-apps/plugin-dialog';
 import QRCode from 'react-qr-code';
 import './App.css';
 
+// Every command and event is scoped to an account; this UI drives a single one
+const ACCOUNT_ID = 'default';
+const accountEvent = (name) => `${name}::${ACCOUNT_ID}`;
+
+// Commands reject with { kind, message }; fall back to the raw value for anything else
+const errorMessage = (err) => err?.message ?? String(err);
+
 function App() {
   const [screen, setScreen] = useState('qr'); // 'qr' or 'message'
   const [qrCode, setQrCode] = useState('');
@@ -22,18 +29,18 @@ function App() {
     const setup = async () => {
       // Set up listeners FIRST before initializing WhatsApp
       // (prevents missing auth-success on reconnection from saved session)
-      const qrUnlisten = await listen('qr-code', (event) => {
+      const qrUnlisten = await listen(accountEvent('qr-code'), (event) => {
         console.log('QR Code received:', event.payload);
         setQrCode(event.payload.code);
         setError('');
       });
 
-      const authUnlisten = await listen('auth-success', async () => {
+      const authUnlisten = await listen(accountEvent('auth-success'), async () => {
         console.log('Authentication successful!');
         
         setTimeout(async () => {
           try {
-            const ready = await invoke('is_bot_ready');
+            const ready = await invoke('is_bot_ready', { accountId: ACCOUNT_ID });
             setIsReady(ready);
             if (ready) {
               setScreen('message');
@@ -50,7 +57,7 @@ function App() {
         }, 2000);
       });
 
-      const logoutUnlisten = await listen('logged-out', () => {
+      const logoutUnlisten = await listen(accountEvent('logged-out'), () => {
         console.log('Logged out - switching to QR screen');
         setIsReady(false);
         setScreen('qr');
@@ -58,22 +65,41 @@ function App() {
         setError('Session expired. Please scan QR code again.');
       });
 
+      const initTimeoutUnlisten = await listen(accountEvent('init-timeout'), () => {
+        console.log('Pairing timed out - connection stopped');
+        setQrCode('');
+        setError('Pairing timed out. Restart the app to get a new QR code.');
+      });
+
       // NOW initialize WhatsApp (listeners are ready to catch events)
       try {
         setLoading(true);
-        await invoke('init_whatsapp');
+        await invoke('init_whatsapp', { accountId: ACCOUNT_ID });
         console.log('WhatsApp initialization started');
       } catch (err) {
-        console.error('Failed to initialize WhatsApp:', err);
-        setError(`Failed to initialize: ${err}`);
+        if (err?.kind === 'AlreadyInitialized') {
+          // Effect ran twice (e.g. StrictMode); the existing bot task keeps going
+          console.log('WhatsApp already initialized');
+        } else {
+          console.error('Failed to initialize WhatsApp:', err);
+          setError(`Failed to initialize: ${errorMessage(err)}`);
+        }
       } finally {
         setLoading(false);
       }
 
+      // The QR may have been emitted before the qr-code listener was registered
+      try {
+        const currentQr = await invoke('get_current_qr', { accountId: ACCOUNT_ID });
+        if (currentQr) setQrCode((existing) => existing || currentQr);
+      } catch (e) {
+        console.error('Error reading current QR code:', e);
+      }
+
       // Poll for ready state to catch reconnections from saved sessions
       pollInterval = setInterval(async () => {
         try {
-          const ready = await invoke('is_bot_ready');
+          const ready = await invoke('is_bot_ready', { accountId: ACCOUNT_ID });
           if (ready) {
             setIsReady(true);
             setScreen('message');
@@ -89,6 +115,7 @@ function App() {
         qrUnlisten();
         authUnlisten();
         logoutUnlisten();
+        initTimeoutUnlisten();
         if (pollInterval) clearInterval(pollInterval);
       };
     };
@@ -116,7 +143,7 @@ function App() {
       setError('');
       
       // Check if bot is ready before sending
-      const ready = await invoke('is_bot_ready');
+      const ready = await invoke('is_bot_ready', { accountId: ACCOUNT_ID });
       if (!ready) {
         setError('WhatsApp is not fully connected yet. Please wait...');
         setLoading(false);
@@ -128,18 +155,23 @@ function App() {
       if (mediaFile) {
         // Send media message
         const mediaType = getMediaType(mediaFile);
-        messageId = await invoke('send_media_message', {
+        const result = await invoke('send_media_message', {
+          accountId: ACCOUNT_ID,
           contact: contact,
           messageText: message,
           mediaPath: mediaFile,
           mediaType: mediaType,
         });
+        messageId = result.message_id;
       } else {
         // Send text message
-        messageId = await invoke('send_message', {
+        const result = await invoke('send_message', {
+          accountId: ACCOUNT_ID,
           contact: contact,
           message: message,
         });
+        console.log('Message delivered to JID:', result.jid);
+        messageId = result.message_id;
       }
 
       console.log('Message sent with ID:', messageId);
@@ -153,7 +185,7 @@ function App() {
       setTimeout(() => setSuccess(''), 3000);
     } catch (err) {
       console.error('Failed to send message:', err);
-      setError(`Failed to send message: ${err}`);
+      setError(`Failed to send message: ${errorMessage(err)}`);
     } finally {
       setLoading(false);
     }
@@ -166,7 +198,7 @@ function App() {
         filters: [
           {
             name: 'Media Files',
-            extensions: ['jpg', 'jpeg', 'png', 'gif', 'webp', 'mp4', 'mov', 'avi', 'pdf', 'docx', 'xlsx', 'txt', 'zip']
+            extensions: ['jpg', 'jpeg', 'png', 'gif', 'webp', 'mp4', 'mov', 'avi', 'mp3', 'ogg', 'opus', 'wav', 'm4a', 'pdf', 'docx', 'xlsx', 'txt', 'zip']
           }
         ]
       });
@@ -192,7 +224,7 @@ function App() {
       return 'image';
     } else if (['mp4', 'mov', 'avi', 'mkv'].includes(extension)) {
       return 'video';
-    } else if (['mp3', 'ogg', 'wav', 'm4a'].includes(extension)) {
+    } else if (['mp3', 'ogg', 'opus', 'wav', 'm4a'].includes(extension)) {
       return 'audio';
     } else {
       return 'document';